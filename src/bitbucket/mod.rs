@@ -1,43 +1,199 @@
-//! This module contains methods for connecting to the Bitbucket Server REST API.
+//! This module contains methods for connecting to the Bitbucket Server and Bitbucket Cloud REST APIs.
 
 mod read_bitbucket;
+mod read_bitbucket_cloud;
 
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use typed_builder::TypedBuilder;
+use ureq::Agent;
 
-use crate::errors;
+use crate::{errors, provider::Pages};
 
 /// A BitbucketRepository result type
-#[derive(TypedBuilder, Clone, Debug)]
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
 pub struct BitbucketRepository {
     #[builder(setter(into))]
     pub name: String,
-    #[builder(setter(into))]
-    pub git_url: String,
+    /// The name in urls, e.g. "my-repo" for "My Repo"
+    #[builder(default)]
+    pub slug: Option<String>,
+    /// The id on the server, a number or a UUID depending on the provider
+    #[builder(default)]
+    pub id: Option<String>,
+    #[builder(default)]
+    pub description: Option<String>,
+    /// The name of the project, group or organization
+    #[builder(default)]
+    pub project_name: Option<String>,
+    /// Read-only on the server and no longer maintained
+    #[builder(default)]
+    #[serde(default)]
+    pub archived: bool,
+    /// Whether the repository may be forked, when the provider has a fork policy
+    #[builder(default)]
+    pub forkable: Option<bool>,
+    /// Readable without authentication
+    #[builder(default)]
+    #[serde(default)]
+    pub public: bool,
+    #[builder(default)]
+    pub ssh_url: Option<String>,
+    #[builder(default)]
+    pub http_url: Option<String>,
+    /// The owner of a personal repository
+    #[builder(default)]
+    pub owner: Option<String>,
+    /// The default branch, when the listing includes it
+    #[builder(default)]
+    pub default_branch: Option<String>,
+}
+
+impl BitbucketRepository {
+    /// The directory of a local clone, the slug when it differs from the name by more than case,
+    /// e.g. for names with spaces
+    pub fn directory_name(&self) -> &str {
+        match &self.slug {
+            Some(slug) if !slug.is_empty() && !slug.eq_ignore_ascii_case(&self.name) => slug,
+            _ => &self.name,
+        }
+    }
 }
 
-#[derive(TypedBuilder)]
+#[derive(TypedBuilder, Clone)]
 pub struct BitbucketCredentials {
     #[builder(setter(into))]
     username: String,
-    #[builder(setter(into))]
-    password: String,
+    secret: BitbucketSecret,
+}
+
+impl BitbucketCredentials {
+    /// The username and password (or token) for plaintext git authentication over HTTPS
+    pub(crate) fn userpass(&self) -> (&str, &str) {
+        match &self.secret {
+            BitbucketSecret::Password(secret) | BitbucketSecret::Token(secret) => {
+                (&self.username, secret)
+            }
+        }
+    }
 }
 
-/// Query all repositories per project from the Bitbucket REST API
-pub fn all_repositories(
-    bitbucket_root_url: &str,
+/// The secret used to authenticate against Bitbucket
+#[derive(Clone)]
+pub enum BitbucketSecret {
+    /// A password, sent as Basic authorization
+    Password(String),
+    /// An HTTP access token, sent as Bearer authorization
+    Token(String),
+}
+
+/// The Bitbucket installation to query repositories from
+#[derive(Clone, Debug)]
+pub enum BitbucketHost {
+    /// A Bitbucket Server instance at the given root url
+    Server { root_url: String, agent: Agent },
+    /// A Bitbucket Cloud workspace, queried through the given API root url
+    Cloud {
+        api_url: String,
+        workspace: String,
+        agent: Agent,
+    },
+}
+
+impl BitbucketHost {
+    /// The API root url of Bitbucket Cloud
+    pub const CLOUD_API_URL: &'static str = "https://api.bitbucket.org";
+}
+
+/// Query the repositories with their project from the Bitbucket REST API, a page at a time
+pub fn repository_pages<'a>(
+    host: &'a BitbucketHost,
+    credentials: &'a BitbucketCredentials,
+    limit: u32,
+) -> Pages<'a> {
+    match host {
+        BitbucketHost::Server { root_url, agent } => {
+            read_bitbucket::repository_pages(agent, root_url, credentials, limit)
+        }
+        BitbucketHost::Cloud {
+            api_url,
+            workspace,
+            agent,
+        } => read_bitbucket_cloud::repository_pages(agent, api_url, workspace, credentials, limit),
+    }
+}
+
+/// Query the default branch of a repository from the Bitbucket REST API
+pub fn default_branch(
+    host: &BitbucketHost,
     credentials: &BitbucketCredentials,
-    limit: i16,
-) -> errors::Result<HashMap<String, Vec<BitbucketRepository>>> {
-    read_bitbucket::get_all_repositories(bitbucket_root_url, credentials, limit)
+    project: &str,
+    slug: &str,
+) -> errors::Result<Option<String>> {
+    match host {
+        BitbucketHost::Server { root_url, agent } => {
+            read_bitbucket::get_default_branch(agent, root_url, credentials, project, slug)
+        }
+        BitbucketHost::Cloud {
+            api_url,
+            workspace,
+            agent,
+        } => read_bitbucket_cloud::get_default_branch(agent, api_url, workspace, credentials, slug),
+    }
 }
 
 impl From<&BitbucketCredentials> for String {
     fn from(credentials: &BitbucketCredentials) -> Self {
-        format!(
-            "Basic {}",
-            base64::encode(format!("{}:{}", credentials.username, credentials.password))
-        )
+        match &credentials.secret {
+            BitbucketSecret::Password(password) => format!(
+                "Basic {}",
+                base64::encode(format!("{}:{}", credentials.username, password))
+            ),
+            BitbucketSecret::Token(token) => format!("Bearer {}", token),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_authorization() {
+        let credentials = BitbucketCredentials::builder()
+            .username("user")
+            .secret(BitbucketSecret::Password(String::from("secret")))
+            .build();
+
+        assert_eq!(String::from(&credentials), "Basic dXNlcjpzZWNyZXQ=");
+    }
+
+    #[test]
+    fn token_authorization() {
+        let credentials = BitbucketCredentials::builder()
+            .username("user")
+            .secret(BitbucketSecret::Token(String::from("NjM4MzY0")))
+            .build();
+
+        assert_eq!(String::from(&credentials), "Bearer NjM4MzY0");
+    }
+
+    #[test]
+    fn directory_name() {
+        let repository = |name: &str, slug: &str| {
+            BitbucketRepository::builder()
+                .name(name)
+                .slug(Some(slug.to_owned()))
+                .build()
+        };
+
+        assert_eq!(repository("My Repo", "my-repo").directory_name(), "my-repo");
+        assert_eq!(repository("ATLAS", "atlas").directory_name(), "ATLAS");
+        assert_eq!(
+            BitbucketRepository::builder()
+                .name("api")
+                .build()
+                .directory_name(),
+            "api"
+        );
     }
 }
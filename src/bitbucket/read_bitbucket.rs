@@ -7,55 +7,84 @@ use ureq::Agent;
 use crate::{
     bitbucket::{BitbucketCredentials, BitbucketRepository},
     errors,
+    provider::{get, get_optional, Pages},
 };
 
-/// Query all repositories per project from the Bitbucket REST API
-pub fn get_all_repositories(
-    bitbucket_root_url: &str,
-    credentials: &BitbucketCredentials,
-    limit: i16,
-) -> errors::Result<HashMap<String, Vec<BitbucketRepository>>> {
-    let agent: Agent = ureq::AgentBuilder::new().build();
+/// Query the repositories with their project from the Bitbucket REST API, a page at a time
+pub fn repository_pages<'a>(
+    agent: &'a Agent,
+    bitbucket_root_url: &'a str,
+    credentials: &'a BitbucketCredentials,
+    limit: u32,
+) -> Pages<'a> {
     let authorization = String::from(credentials);
+    let page_url = move |start: u32| {
+        format!(
+            "{}/rest/api/latest/repos?start={}&limit={}",
+            bitbucket_root_url, start, limit
+        )
+    };
 
-    let mut repos: HashMap<String, Vec<BitbucketRepository>> = HashMap::new();
-    let mut start: i16 = 0;
-    loop {
-        let response = agent
-            .get(format!("{}/rest/api/latest/repos", bitbucket_root_url).as_str())
-            .query("start", &i16::to_string(&start))
-            .query("limit", &i16::to_string(&limit))
-            .set("Authorization", &authorization)
-            .call()
-            .map_err(|e| match e {
-                ureq::Error::Status(401, _) => errors::Error::InvalidUsernamePassword,
-                _ => errors::Error::FailedToDoHttpCall(Box::new(e)),
-            })?;
-
+    Pages::new([page_url(0)], move |url| {
+        let response = get(agent, url, &authorization)?;
         let json: RemoteEnvelope = response
             .into_json()
             .map_err(errors::Error::FailedToParseJSON)?;
 
-        for value in &json.values {
-            if value.project.project_type == "NORMAL" {
-                let project_key = &value.project.key;
+        let page = json
+            .values
+            .iter()
+            .map(|value| {
                 let data = BitbucketRepository::builder()
                     .name(&value.name)
-                    .git_url(value.ssh_url().ok_or(errors::Error::SshUrlMissing)?)
+                    .slug(Some(value.slug.to_owned()))
+                    .id(value.id.map(|id| id.to_string()))
+                    .description(value.description.clone())
+                    .project_name(value.project.name.clone())
+                    .archived(value.archived)
+                    .forkable(value.forkable)
+                    .public(value.public)
+                    .ssh_url(value.ssh_url().map(str::to_owned))
+                    .http_url(value.http_url().map(str::to_owned))
+                    .owner(value.project.personal_owner())
                     .build();
-                repos
-                    .entry(project_key.to_owned())
-                    .or_insert_with(Vec::new)
-                    .push(data);
-            }
-        }
-        if json.is_last_page {
-            break;
-        }
-        start = json.next_page_start.unwrap()
-    }
+                (value.project.key.to_owned(), data)
+            })
+            .collect();
+        let next = match json.is_last_page {
+            true => None,
+            false => json.next_page_start.map(page_url),
+        };
+
+        Ok((page, next))
+    })
+}
+
+/// Query the default branch of a repository from the Bitbucket REST API
+pub fn get_default_branch(
+    agent: &Agent,
+    bitbucket_root_url: &str,
+    credentials: &BitbucketCredentials,
+    project: &str,
+    slug: &str,
+) -> errors::Result<Option<String>> {
+    let authorization = String::from(credentials);
 
-    Ok(repos)
+    let url = format!(
+        "{}/rest/api/latest/projects/{}/repos/{}/default-branch",
+        bitbucket_root_url, project, slug
+    );
+    // An empty repository has no default branch yet
+    let response = match get_optional(agent, &url, &authorization)? {
+        Some(response) if response.status() != 204 => response,
+        _ => return Ok(None),
+    };
+
+    let json: RemoteBranch = response
+        .into_json()
+        .map_err(errors::Error::FailedToParseJSON)?;
+
+    Ok(Some(json.display_id))
 }
 
 /// The outer envelope from the JSON reply containing pagination and data (values)
@@ -63,7 +92,7 @@ pub fn get_all_repositories(
 #[serde(rename_all = "camelCase")]
 struct RemoteEnvelope {
     is_last_page: bool,
-    next_page_start: Option<i16>,
+    next_page_start: Option<u32>,
     values: Vec<RemoteRepository>,
 }
 
@@ -71,6 +100,15 @@ struct RemoteEnvelope {
 #[derive(Deserialize, Debug)]
 struct RemoteRepository {
     name: String,
+    slug: String,
+    id: Option<u64>,
+    description: Option<String>,
+    /// Only on Bitbucket Server 8.0 and later
+    #[serde(default)]
+    archived: bool,
+    forkable: Option<bool>,
+    #[serde(default)]
+    public: bool,
     project: RemoteProject,
     links: HashMap<String, Vec<RemoteLink>>,
 }
@@ -78,12 +116,18 @@ struct RemoteRepository {
 impl RemoteRepository {
     /// Get the ssh_url from the "clone" link
     fn ssh_url(&self) -> Option<&str> {
+        self.clone_url("ssh")
+    }
+
+    /// Get the http_url from the "clone" link
+    fn http_url(&self) -> Option<&str> {
+        self.clone_url("http")
+    }
+
+    fn clone_url(&self, name: &str) -> Option<&str> {
         let clone = &self.links.get("clone");
         if let Some(clone) = clone {
-            if let Some(link) = clone
-                .iter()
-                .find(|link| link.name == Some("ssh".to_owned()))
-            {
+            if let Some(link) = clone.iter().find(|link| link.name.as_deref() == Some(name)) {
                 return Some(&link.href);
             }
         }
@@ -94,8 +138,35 @@ impl RemoteRepository {
 #[derive(Deserialize, Debug)]
 struct RemoteProject {
     key: String,
+    name: Option<String>,
     #[serde(alias = "type")]
     project_type: String,
+    owner: Option<RemoteOwner>,
+}
+
+impl RemoteProject {
+    /// Get the owner slug of a personal project, e.g. "alice" for project "~ALICE"
+    fn personal_owner(&self) -> Option<String> {
+        if self.project_type != "PERSONAL" {
+            return None;
+        }
+        match &self.owner {
+            Some(owner) => Some(owner.slug.to_owned()),
+            None => Some(self.key.trim_start_matches('~').to_lowercase()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct RemoteOwner {
+    slug: String,
+}
+
+/// A branch, e.g. the default branch of a repository
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RemoteBranch {
+    display_id: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -120,13 +191,27 @@ mod tests {
         "#;
         let result: RemoteEnvelope = serde_json::from_str(json).unwrap();
 
-        assert_eq!(result.is_last_page, true);
-        assert_eq!(result.size, 1);
-        assert_eq!(result.limit, 2);
+        assert!(result.is_last_page);
         assert!(result.next_page_start.is_none());
         assert!(result.values.is_empty());
     }
 
+    #[test]
+    fn deserialize_beyond_i16() {
+        let json = r#"
+        {
+            "isLastPage": false,
+            "size": 250,
+            "limit": 250,
+            "nextPageStart": 40250,
+            "values": []
+        }
+        "#;
+        let result: RemoteEnvelope = serde_json::from_str(json).unwrap();
+
+        assert_eq!(result.next_page_start, Some(40250));
+    }
+
     #[test]
     fn deserialize_with_repositories() {
         let json = r#"
@@ -136,9 +221,16 @@ mod tests {
             "limit": 2,
             "values": [
                 {
+                    "slug": "atlas",
+                    "id": 1,
                     "name": "ATLAS",
+                    "description": "The atlas",
+                    "archived": true,
+                    "forkable": true,
+                    "public": false,
                     "project": {
                         "key": "ATLAS",
+                        "name": "Atlas",
                         "type": "NORMAL"
                     },
                     "links": {
@@ -161,25 +253,67 @@ mod tests {
         "#;
         let result: RemoteEnvelope = serde_json::from_str(json).unwrap();
 
-        assert_eq!(result.is_last_page, true);
-        assert_eq!(result.size, 1);
-        assert_eq!(result.limit, 2);
+        assert!(result.is_last_page);
         assert!(result.next_page_start.is_none());
         assert_eq!(result.values.len(), 1);
 
-        let result: &RemoteRepository = result.values.get(0).unwrap();
+        let result: &RemoteRepository = result.values.first().unwrap();
 
         assert_eq!(result.name, "ATLAS");
+        assert_eq!(result.slug, "atlas");
+        assert_eq!(result.description.as_deref(), Some("The atlas"));
+        assert!(result.archived);
+        assert_eq!(result.forkable, Some(true));
         assert_eq!(result.project.key, "ATLAS");
+        assert_eq!(result.project.name.as_deref(), Some("Atlas"));
         assert_eq!(result.project.project_type, "NORMAL");
         assert_eq!(result.links.get("clone").unwrap().len(), 1);
 
-        let result: &RemoteLink = result.links.get("clone").unwrap().get(0).unwrap();
+        let result: &RemoteLink = result.links.get("clone").unwrap().first().unwrap();
 
         assert_eq!(result.href, "href");
         assert_eq!(result.name, Some("name".to_string()));
     }
 
+    #[test]
+    fn personal_owner() {
+        let json = r#"
+        {
+            "key": "~ALICE",
+            "type": "PERSONAL",
+            "owner": {
+                "name": "alice",
+                "slug": "alice"
+            }
+        }
+        "#;
+        let result: RemoteProject = serde_json::from_str(json).unwrap();
+
+        assert_eq!(result.personal_owner(), Some("alice".to_owned()));
+        assert_eq!(
+            remote_repository("clone", "href", None)
+                .project
+                .personal_owner(),
+            None
+        );
+    }
+
+    #[test]
+    fn deserialize_default_branch() {
+        let json = r#"
+        {
+            "id": "refs/heads/trunk",
+            "displayId": "trunk",
+            "type": "BRANCH",
+            "latestCommit": "8d51122def5632836d1cb1026e879069e10a1e13",
+            "isDefault": true
+        }
+        "#;
+        let result: RemoteBranch = serde_json::from_str(json).unwrap();
+
+        assert_eq!(result.display_id, "trunk");
+    }
+
     #[test]
     fn ssh_url() {
         assert_eq!(
@@ -197,11 +331,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn http_url() {
+        assert_eq!(
+            remote_repository("clone", "href", Some("http")).http_url(),
+            Some("href")
+        );
+        assert_eq!(
+            remote_repository("clone", "href", Some("ssh")).http_url(),
+            None
+        );
+    }
+
     fn remote_repository(link_name: &str, href: &str, href_name: Option<&str>) -> RemoteRepository {
         let name = String::from("name");
         let project = RemoteProject {
             key: String::from("ATLAS"),
+            name: None,
             project_type: String::from("NORMAL"),
+            owner: None,
         };
         let mut links: HashMap<String, Vec<RemoteLink>> = HashMap::new();
         links.insert(
@@ -220,7 +368,13 @@ mod tests {
         );
 
         RemoteRepository {
+            slug: name.to_lowercase(),
             name,
+            id: Some(1),
+            description: None,
+            archived: false,
+            forkable: None,
+            public: false,
             project,
             links,
         }
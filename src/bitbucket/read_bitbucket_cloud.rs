@@ -0,0 +1,269 @@
+//! Private implementation for querying the Bitbucket Cloud REST API
+
+use serde::Deserialize;
+use ureq::Agent;
+
+use crate::{
+    bitbucket::{BitbucketCredentials, BitbucketRepository},
+    errors,
+    provider::{get, get_optional, Pages},
+};
+
+/// The maximum page length accepted by Bitbucket Cloud
+const MAX_PAGE_LENGTH: u32 = 100;
+
+/// Query the repositories with their project in a workspace from the Bitbucket Cloud REST API,
+/// a page at a time
+pub fn repository_pages<'a>(
+    agent: &'a Agent,
+    api_url: &str,
+    workspace: &str,
+    credentials: &BitbucketCredentials,
+    limit: u32,
+) -> Pages<'a> {
+    let authorization = String::from(credentials);
+    let url = format!(
+        "{}/2.0/repositories/{}?pagelen={}",
+        api_url,
+        workspace,
+        limit.min(MAX_PAGE_LENGTH)
+    );
+
+    Pages::new([url], move |url| {
+        let response = get(agent, url, &authorization)?;
+        let json: RemoteEnvelope = response
+            .into_json()
+            .map_err(errors::Error::FailedToParseJSON)?;
+
+        let page = json
+            .values
+            .iter()
+            .filter_map(|value| {
+                let project = value.project.as_ref()?;
+                let data = BitbucketRepository::builder()
+                    .name(&value.name)
+                    .slug(value.slug.clone())
+                    .id(value.uuid.clone())
+                    .description(value.description.clone().filter(|d| !d.is_empty()))
+                    .project_name(project.name.clone())
+                    .forkable(
+                        value
+                            .fork_policy
+                            .as_deref()
+                            .map(|policy| policy != "no_forks"),
+                    )
+                    .public(value.is_private == Some(false))
+                    .ssh_url(value.ssh_url().map(str::to_owned))
+                    .http_url(value.http_url().map(str::to_owned))
+                    .default_branch(
+                        value
+                            .mainbranch
+                            .as_ref()
+                            .map(|branch| branch.name.to_owned()),
+                    )
+                    .build();
+                Some((project.key.to_owned(), data))
+            })
+            .collect();
+
+        Ok((page, json.next))
+    })
+}
+
+/// Query the main branch of a repository in a workspace from the Bitbucket Cloud REST API
+pub fn get_default_branch(
+    agent: &Agent,
+    api_url: &str,
+    workspace: &str,
+    credentials: &BitbucketCredentials,
+    slug: &str,
+) -> errors::Result<Option<String>> {
+    let authorization = String::from(credentials);
+
+    let url = format!("{}/2.0/repositories/{}/{}", api_url, workspace, slug);
+    let response = match get_optional(agent, &url, &authorization)? {
+        Some(response) => response,
+        None => return Ok(None),
+    };
+
+    let json: RemoteMainBranch = response
+        .into_json()
+        .map_err(errors::Error::FailedToParseJSON)?;
+
+    Ok(json.mainbranch.map(|branch| branch.name))
+}
+
+/// The outer envelope from the JSON reply containing pagination and data (values)
+#[derive(Deserialize, Debug)]
+struct RemoteEnvelope {
+    next: Option<String>,
+    values: Vec<RemoteRepository>,
+}
+
+/// A Remote Repository representation containing the clone links
+#[derive(Deserialize, Debug)]
+struct RemoteRepository {
+    name: String,
+    slug: Option<String>,
+    uuid: Option<String>,
+    description: Option<String>,
+    is_private: Option<bool>,
+    /// One of "allow_forks", "no_public_forks" and "no_forks"
+    fork_policy: Option<String>,
+    project: Option<RemoteProject>,
+    mainbranch: Option<RemoteBranch>,
+    links: RemoteLinks,
+}
+
+impl RemoteRepository {
+    /// Get the ssh_url from the "clone" link
+    fn ssh_url(&self) -> Option<&str> {
+        self.clone_url("ssh")
+    }
+
+    /// Get the https url from the "clone" link
+    fn http_url(&self) -> Option<&str> {
+        self.clone_url("https")
+    }
+
+    fn clone_url(&self, name: &str) -> Option<&str> {
+        self.links
+            .clone
+            .iter()
+            .find(|link| link.name.as_deref() == Some(name))
+            .map(|link| link.href.as_str())
+    }
+}
+
+/// A repository with its main branch
+#[derive(Deserialize, Debug)]
+struct RemoteMainBranch {
+    mainbranch: Option<RemoteBranch>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RemoteBranch {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct RemoteProject {
+    key: String,
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RemoteLinks {
+    #[serde(default)]
+    clone: Vec<RemoteLink>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RemoteLink {
+    href: String,
+    name: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_last_page() {
+        let json = r#"
+        {
+            "pagelen": 10,
+            "page": 2,
+            "size": 0,
+            "values": []
+        }
+        "#;
+        let result: RemoteEnvelope = serde_json::from_str(json).unwrap();
+
+        assert!(result.next.is_none());
+        assert!(result.values.is_empty());
+    }
+
+    #[test]
+    fn deserialize_with_repositories() {
+        let json = r#"
+        {
+            "pagelen": 1,
+            "page": 1,
+            "size": 2,
+            "next": "https://api.bitbucket.org/2.0/repositories/atlas?pagelen=1&page=2",
+            "values": [
+                {
+                    "type": "repository",
+                    "name": "Atlas Core",
+                    "slug": "atlas-core",
+                    "project": {
+                        "type": "project",
+                        "key": "ATLAS",
+                        "name": "Atlas"
+                    },
+                    "links": {
+                        "self": {
+                            "href": "https://api.bitbucket.org/2.0/repositories/atlas/atlas-core"
+                        },
+                        "clone": [
+                            {
+                                "href": "https://user@bitbucket.org/atlas/atlas-core.git",
+                                "name": "https"
+                            },
+                            {
+                                "href": "git@bitbucket.org:atlas/atlas-core.git",
+                                "name": "ssh"
+                            }
+                        ]
+                    }
+                }
+            ]
+        }
+        "#;
+        let result: RemoteEnvelope = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            result.next,
+            Some("https://api.bitbucket.org/2.0/repositories/atlas?pagelen=1&page=2".to_string())
+        );
+        assert_eq!(result.values.len(), 1);
+
+        let result: &RemoteRepository = result.values.first().unwrap();
+
+        assert_eq!(result.name, "Atlas Core");
+        assert_eq!(result.slug.as_deref(), Some("atlas-core"));
+        assert_eq!(result.project.as_ref().unwrap().key, "ATLAS");
+        assert_eq!(
+            result.project.as_ref().unwrap().name.as_deref(),
+            Some("Atlas")
+        );
+        assert_eq!(result.links.clone.len(), 2);
+        assert_eq!(
+            result.ssh_url(),
+            Some("git@bitbucket.org:atlas/atlas-core.git")
+        );
+    }
+
+    #[test]
+    fn ssh_url_missing() {
+        let repository = RemoteRepository {
+            name: String::from("name"),
+            slug: None,
+            uuid: None,
+            description: None,
+            is_private: None,
+            fork_policy: None,
+            project: None,
+            mainbranch: None,
+            links: RemoteLinks {
+                clone: vec![RemoteLink {
+                    href: String::from("href"),
+                    name: Some(String::from("https")),
+                }],
+            },
+        };
+
+        assert_eq!(repository.ssh_url(), None);
+    }
+}
@@ -1,27 +1,81 @@
 //! This module contains everything to do with parsing commandline arguments and transforming them into Commands.
 
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgEnum, Args, Parser, Subcommand};
 use gitbucket::{
-    bitbucket::BitbucketCredentials,
+    bitbucket::{BitbucketCredentials, BitbucketHost, BitbucketSecret},
     errors,
-    git::{exclusions::Exclusions, Git},
+    git::{
+        branches::MainBranches,
+        exclusions::Exclusions,
+        inventory::{InventoryCache, DEFAULT_INVENTORY_TTL_SECONDS},
+        known_hosts::{HostKeyChecking, KnownHosts},
+        personal::PersonalRepositories,
+        progress::Progress,
+        ssh::SshKeys,
+        ArchivedRepositories, CloneProtocol, Git, DEFAULT_JOBS,
+    },
+    network::{Network, DEFAULT_TIMEOUT_SECONDS},
+    provider::{gitea::Gitea, github::GitHub, gitlab::GitLab, Provider},
+    retry::{RetryPolicy, DEFAULT_BACKOFF_MS, DEFAULT_RETRIES},
+};
+use is_terminal::IsTerminal;
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::PathBuf, time::Duration};
+
+use crate::{
+    config::{ConfigFile, Profile},
+    exit::FailOn,
+    output::OutputFormat,
 };
-use std::env;
+
+/// How the results of a command are shown and judged
+pub struct Options {
+    pub output: OutputFormat,
+    pub fail_on: Vec<FailOn>,
+    /// The progress of the command, log lines are written past it
+    pub progress: Progress,
+}
 
 pub enum SubCommand {
     Clone {
         git: Git,
-        bitbucket_root_url: String,
+        provider: Box<dyn Provider>,
         credentials: BitbucketCredentials,
-        exclusions: Exclusions,
+        personal: PersonalRepositories,
+    },
+    ConfigShow {
+        profile: Profile,
     },
     Featured {
         git: Git,
         show_main: bool,
+        remote: Option<(
+            Box<dyn Provider>,
+            BitbucketCredentials,
+            PersonalRepositories,
+        )>,
+    },
+    List {
+        git: Git,
+        provider: Box<dyn Provider>,
+        credentials: BitbucketCredentials,
+        personal: PersonalRepositories,
+        projects: Vec<String>,
+        selected: bool,
+        default_branches: bool,
+    },
+    Prune {
+        git: Git,
+        provider: Box<dyn Provider>,
+        credentials: BitbucketCredentials,
+        personal: PersonalRepositories,
+        relocate: bool,
+        delete: bool,
     },
     Pull {
         git: Git,
         show_errors: bool,
+        credentials: Option<BitbucketCredentials>,
     },
     Status {
         git: Git,
@@ -29,44 +83,376 @@ pub enum SubCommand {
 }
 
 impl SubCommand {
-    pub fn from_arguments() -> errors::Result<SubCommand> {
+    /// Parse the commandline into the command and how its results are shown and judged
+    pub fn from_arguments() -> errors::Result<(SubCommand, Options)> {
         let cli: Cli = Cli::parse();
 
+        // Commandline and environment settings override the configuration file
+        let file_profile =
+            ConfigFile::read(cli.args.config.as_deref())?.profile(cli.args.profile.clone())?;
+        let command_profile = match &cli.command {
+            CliCommands::Clone { connection }
+            | CliCommands::List { connection, .. }
+            | CliCommands::Prune { connection, .. }
+            | CliCommands::Featured { connection, .. }
+            | CliCommands::Pull { connection, .. }
+            | CliCommands::Config {
+                command: ConfigCommands::Show { connection },
+            } => connection.profile(),
+            _ => Profile::default(),
+        };
+        let profile = cli
+            .args
+            .profile()
+            .or(command_profile)
+            .or(file_profile)
+            .with_defaults()?;
+
+        let exclusions = Exclusions::new(
+            profile.included_projects.as_deref().unwrap_or_default(),
+            profile.excluded_projects.as_deref().unwrap_or_default(),
+        )?
+        .only(&cli.args.only)?;
+        let network = Network::builder()
+            .ca_file(profile.ca_file.as_ref().map(PathBuf::from))
+            .client_certificate(profile.client_certificate.as_ref().map(PathBuf::from))
+            .client_key(profile.client_key.as_ref().map(PathBuf::from))
+            .proxy(profile.proxy.clone())
+            .no_proxy(profile.no_proxy.clone().unwrap_or_default())
+            .timeout(Duration::from_secs(
+                profile.timeout.unwrap_or(DEFAULT_TIMEOUT_SECONDS),
+            ))
+            .build();
+        network.configure_git()?;
+        // libgit2 has no client certificates, git over https would connect without it
+        let clone_protocol = profile.clone_protocol.unwrap_or_default();
+        if profile.client_certificate.is_some() && clone_protocol != CloneProtocol::Ssh {
+            return Err(errors::Error::ClientCertificateOverHttps);
+        }
+        // The bars are drawn on stderr
+        let progress =
+            Progress::new(cli.args.output == OutputFormat::Text && io::stderr().is_terminal());
         let git = Git::builder()
-            .root_directory(cli.args.directory)
-            .private_key_location(cli.args.private_key)
+            .root_directory(profile.directory.clone().unwrap_or_default())
+            .ssh_keys(SshKeys::new(profile.ssh_keys()))
+            .known_hosts(KnownHosts::new(
+                profile.host_key_checking.unwrap_or_default(),
+                &profile.known_hosts_files(),
+            )?)
             .dry_run(cli.args.dry_run)
+            .clone_protocol(clone_protocol)
+            .archived(profile.archived.unwrap_or_default())
+            .exclusions(exclusions)
+            .main_branches(MainBranches::new(
+                profile.main_branches.as_deref().unwrap_or_default(),
+            )?)
+            .jobs(profile.jobs.unwrap_or(DEFAULT_JOBS))
+            .network(network.clone())
+            .progress(progress.clone())
+            .retry(
+                RetryPolicy::builder()
+                    .retries(profile.retries.unwrap_or(DEFAULT_RETRIES))
+                    .backoff(Duration::from_millis(
+                        profile.retry_backoff.unwrap_or(DEFAULT_BACKOFF_MS),
+                    ))
+                    .build(),
+            )
+            .project_depth(project_depth(&profile))
+            .inventory(
+                InventoryCache::builder()
+                    .source(inventory_source(&profile))
+                    .ttl(Duration::from_secs(
+                        profile
+                            .inventory_ttl
+                            .unwrap_or(DEFAULT_INVENTORY_TTL_SECONDS),
+                    ))
+                    .offline(cli.args.offline)
+                    .build(),
+            )
             .build();
+        let offline = cli.args.offline;
 
         let command = match cli.command {
-            CliCommands::Clone {
-                user,
-                password,
-                bitbucket_root_url,
-            } => {
-                let password = password
-                    .unwrap_or_else(|| rpassword::prompt_password("Bitbucket password: ").unwrap());
-                let credentials = BitbucketCredentials::builder()
-                    .username(user)
-                    .password(password)
-                    .build();
-                let exclusions = Exclusions::from(cli.args.excluded_projects);
+            CliCommands::Clone { .. } => {
+                let (provider, credentials, personal) = connect(&profile, &network, offline)?;
                 SubCommand::Clone {
                     git,
-                    bitbucket_root_url,
+                    provider,
+                    credentials,
+                    personal,
+                }
+            }
+            CliCommands::Config {
+                command: ConfigCommands::Show { .. },
+            } => SubCommand::ConfigShow { profile },
+            CliCommands::List {
+                project,
+                selected,
+                default_branches,
+                ..
+            } => {
+                let (provider, credentials, personal) = connect(&profile, &network, offline)?;
+                SubCommand::List {
+                    git,
+                    provider,
                     credentials,
-                    exclusions,
+                    personal,
+                    projects: project,
+                    selected,
+                    default_branches,
                 }
             }
-            CliCommands::Featured { show_main } => SubCommand::Featured { git, show_main },
-            CliCommands::Pull { show_errors } => SubCommand::Pull { git, show_errors },
+            CliCommands::Prune {
+                relocate, delete, ..
+            } => {
+                let (provider, credentials, personal) = connect(&profile, &network, offline)?;
+                SubCommand::Prune {
+                    git,
+                    provider,
+                    credentials,
+                    personal,
+                    relocate,
+                    delete,
+                }
+            }
+            CliCommands::Featured {
+                show_main,
+                remote_default_branch,
+                ..
+            } => {
+                let remote = match remote_default_branch {
+                    true => Some(connect(&profile, &network, offline)?),
+                    false => None,
+                };
+                SubCommand::Featured {
+                    git,
+                    show_main,
+                    remote,
+                }
+            }
+            CliCommands::Pull { show_errors, .. } => SubCommand::Pull {
+                git,
+                show_errors,
+                credentials: fetch_credentials(&profile)?,
+            },
             CliCommands::Status => SubCommand::Status { git },
         };
 
-        Ok(command)
+        let options = Options {
+            output: cli.args.output,
+            fail_on: cli.args.fail_on,
+            progress,
+        };
+
+        Ok((command, options))
     }
 }
 
+/// Build the provider, the credentials and the personal repository selection, without
+/// prompting for a password when offline
+fn connect(
+    profile: &Profile,
+    network: &Network,
+    offline: bool,
+) -> errors::Result<(
+    Box<dyn Provider>,
+    BitbucketCredentials,
+    PersonalRepositories,
+)> {
+    let user = profile.user.clone().ok_or(errors::Error::MissingUser)?;
+    let provider = profile.provider.unwrap_or(ProviderKind::Bitbucket).build(
+        profile.root_url.clone(),
+        profile.workspace.clone(),
+        profile.organizations.clone().unwrap_or_default(),
+        network,
+    )?;
+    let personal = PersonalRepositories::new(
+        profile.include_personal.clone(),
+        &user,
+        profile.personal_directory.as_deref().unwrap_or_default(),
+    );
+    let secret = secret(
+        profile.password.clone(),
+        profile.token.clone(),
+        profile.token_file.clone(),
+        !offline,
+    )?;
+    let credentials = BitbucketCredentials::builder()
+        .username(user)
+        .secret(secret)
+        .build();
+
+    Ok((provider, credentials, personal))
+}
+
+/// The credentials for fetching over http(s) when a password or token is configured, without
+/// prompting as fetches fall back to the git credential helper
+fn fetch_credentials(profile: &Profile) -> errors::Result<Option<BitbucketCredentials>> {
+    let user = match &profile.user {
+        Some(user) => user.clone(),
+        None => return Ok(None),
+    };
+    if profile.password.is_none() && profile.token.is_none() && profile.token_file.is_none() {
+        return Ok(None);
+    }
+    let secret = secret(
+        profile.password.clone(),
+        profile.token.clone(),
+        profile.token_file.clone(),
+        false,
+    )?;
+
+    Ok(Some(
+        BitbucketCredentials::builder()
+            .username(user)
+            .secret(secret)
+            .build(),
+    ))
+}
+
+/// The supported hosting providers
+#[derive(ArgEnum, Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    Bitbucket,
+    Github,
+    Gitlab,
+    Gitea,
+}
+
+impl ProviderKind {
+    /// Build the provider from the root url and the workspace or organizations to mirror
+    fn build(
+        self,
+        root_url: Option<String>,
+        workspace: Option<String>,
+        organizations: Vec<String>,
+        network: &Network,
+    ) -> errors::Result<Box<dyn Provider>> {
+        let name = self.name();
+        if !matches!(self, ProviderKind::Bitbucket) && organizations.is_empty() {
+            return Err(errors::Error::MissingOrganization(name));
+        }
+
+        let provider: Box<dyn Provider> = match self {
+            ProviderKind::Bitbucket => match workspace {
+                Some(workspace) => {
+                    let api_url =
+                        root_url.unwrap_or_else(|| BitbucketHost::CLOUD_API_URL.to_owned());
+                    Box::new(BitbucketHost::Cloud {
+                        agent: network.agent(&api_url)?,
+                        api_url,
+                        workspace,
+                    })
+                }
+                None => {
+                    let root_url = root_url.ok_or(errors::Error::MissingRootUrl(name))?;
+                    Box::new(BitbucketHost::Server {
+                        agent: network.agent(&root_url)?,
+                        root_url,
+                    })
+                }
+            },
+            ProviderKind::Github => {
+                let api_url = root_url.unwrap_or_else(|| GitHub::API_URL.to_owned());
+                Box::new(GitHub {
+                    agent: network.agent(&api_url)?,
+                    api_url,
+                    organizations,
+                })
+            }
+            ProviderKind::Gitlab => {
+                let root_url = root_url.unwrap_or_else(|| GitLab::ROOT_URL.to_owned());
+                Box::new(GitLab {
+                    agent: network.agent(&root_url)?,
+                    root_url,
+                    groups: organizations,
+                })
+            }
+            ProviderKind::Gitea => {
+                let root_url = root_url.ok_or(errors::Error::MissingRootUrl(name))?;
+                Box::new(Gitea {
+                    agent: network.agent(&root_url)?,
+                    root_url,
+                    organizations,
+                })
+            }
+        };
+
+        Ok(provider)
+    }
+
+    fn name(self) -> String {
+        self.to_possible_value()
+            .map_or_else(String::new, |value| value.get_name().to_owned())
+    }
+}
+
+/// The number of directories a project spans locally, GitLab subgroups and the personal
+/// directory may span more than one
+fn project_depth(profile: &Profile) -> usize {
+    let provider = match profile.provider {
+        Some(ProviderKind::Gitlab) => GitLab::MAX_GROUP_DEPTH,
+        _ => 1,
+    };
+    let personal = profile
+        .personal_directory
+        .as_deref()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .count();
+    provider.max(personal)
+}
+
+/// What the inventory is a listing of, e.g. "github https://api.github.com rust-lang,tokio-rs"
+fn inventory_source(profile: &Profile) -> String {
+    let provider = profile.provider.unwrap_or(ProviderKind::Bitbucket).name();
+    [
+        Some(provider),
+        profile.root_url.clone(),
+        profile.workspace.clone(),
+        profile.organizations.as_ref().map(|o| o.join(",")),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|part| !part.is_empty())
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Determine the Bitbucket secret, preferring a token over a password, prompted for when allowed
+fn secret(
+    password: Option<String>,
+    token: Option<String>,
+    token_file: Option<String>,
+    prompt: bool,
+) -> errors::Result<BitbucketSecret> {
+    if let Some(token) = token {
+        return Ok(BitbucketSecret::Token(token));
+    }
+    if let Some(token_file) = token_file {
+        let token = fs::read_to_string(&token_file)
+            .map_err(|source| errors::Error::FailedToReadTokenFile {
+                file: token_file.clone(),
+                source,
+            })?
+            .trim()
+            .to_owned();
+        if token.is_empty() {
+            return Err(errors::Error::EmptyTokenFile(token_file));
+        }
+        return Ok(BitbucketSecret::Token(token));
+    }
+    let password = match password {
+        Some(password) => password,
+        None if prompt => rpassword::prompt_password("Bitbucket password: ")
+            .map_err(errors::Error::FailedToPromptPassword)?,
+        None => String::new(),
+    };
+    Ok(BitbucketSecret::Password(password))
+}
+
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
 struct Cli {
@@ -78,39 +464,307 @@ struct Cli {
 
 #[derive(Debug, Args)]
 struct CliArgs {
+    #[clap(
+        long,
+        name = "FILE",
+        help = "Sets the configuration file, default ~/.config/gitbucket/config.toml",
+        env = "GITBUCKET_CONFIG"
+    )]
+    config: Option<String>,
+    #[clap(
+        long,
+        help = "Selects a profile from the configuration file",
+        env = "GITBUCKET_PROFILE"
+    )]
+    profile: Option<String>,
     #[clap(
         short,
         long,
         name = "DIR",
-        help = "Sets the root directory",
-        env = "GITBUCKET_DIRECTORY",
-        default_value = "."
+        help = "Sets the root directory, default .",
+        env = "GITBUCKET_DIRECTORY"
+    )]
+    directory: Option<String>,
+    #[clap(
+        long,
+        name = "KEYFILE",
+        help = "Sets the private key tried after the ssh agent, before ~/.ssh/id_ed25519, id_ecdsa and id_rsa",
+        env = "GITBUCKET_PRIVATE_KEY"
+    )]
+    private_key: Option<String>,
+    #[clap(
+        long,
+        possible_values = &["strict", "accept-new", "off"],
+        help = "Checking of ssh host keys against known_hosts, default strict",
+        env = "GITBUCKET_HOST_KEY_CHECKING"
+    )]
+    host_key_checking: Option<HostKeyChecking>,
+    #[clap(
+        long,
+        name = "KNOWNHOSTS",
+        help = "Known hosts file checked after ~/.ssh/known_hosts",
+        env = "GITBUCKET_KNOWN_HOSTS"
+    )]
+    known_hosts: Option<String>,
+    #[clap(
+        long,
+        name = "CAFILE",
+        help = "PEM file with root certificates trusted next to the built-in ones",
+        env = "GITBUCKET_CA_FILE"
     )]
-    directory: String,
+    ca_file: Option<String>,
     #[clap(
-    long,
-    name = "KEYFILE",
-    help = "Sets the private key location",
-    env = "GITBUCKET_PRIVATE_KEY",
-    default_value_t = CliArgs::default_private_key().unwrap()
+        long,
+        name = "CERTFILE",
+        help = "PEM file with the client certificate for the REST API, requires clone protocol ssh",
+        env = "GITBUCKET_CLIENT_CERTIFICATE"
+    )]
+    client_certificate: Option<String>,
+    #[clap(
+        long,
+        name = "CLIENTKEYFILE",
+        help = "PEM file with the private key of the client certificate, default the certificate file",
+        env = "GITBUCKET_CLIENT_KEY"
+    )]
+    client_key: Option<String>,
+    #[clap(
+        long,
+        name = "URL",
+        help = "Proxy for the REST API and git over https, default $HTTPS_PROXY",
+        env = "GITBUCKET_PROXY"
+    )]
+    proxy: Option<String>,
+    #[clap(
+        long,
+        name = "HOSTS",
+        help = "Comma-separated hosts connected to without the proxy, default $NO_PROXY",
+        env = "GITBUCKET_NO_PROXY"
+    )]
+    no_proxy: Option<String>,
+    #[clap(
+        long,
+        name = "SECONDS",
+        help = "Timeout of connecting and reading responses of the REST API, default 30",
+        env = "GITBUCKET_TIMEOUT"
     )]
-    private_key: String,
+    timeout: Option<u64>,
     #[clap(long, help = "Runs a dry run")]
     dry_run: bool,
     #[clap(
         long,
-        help = "Excluded projects",
+        help = "Uses the inventory of the last listing instead of the server, however old"
+    )]
+    offline: bool,
+    #[clap(
+        long,
+        name = "TTL",
+        help = "Seconds the inventory of the last listing is used instead of the server, default 600",
+        env = "GITBUCKET_INVENTORY_TTL"
+    )]
+    inventory_ttl: Option<u64>,
+    #[clap(
+        long,
+        help = "Included projects/repositories, only these are selected",
+        required = false,
+        env = "GITBUCKET_INCLUDED_PROJECTS"
+    )]
+    included_projects: Option<String>,
+    #[clap(
+        long,
+        help = "Excluded projects/repositories, in order, ! re-includes",
         required = false,
         env = "GITBUCKET_EXCLUDED_PROJECTS"
     )]
     excluded_projects: Option<String>,
+    #[clap(
+        long,
+        name = "PROJECT[/repo]",
+        help = "Only operate on these projects/repositories",
+        multiple_occurrences = true,
+        value_delimiter = ','
+    )]
+    only: Vec<String>,
+    #[clap(
+        long,
+        help = "Main branch patterns, default main,master,develop",
+        env = "GITBUCKET_MAIN_BRANCHES"
+    )]
+    main_branches: Option<String>,
+    #[clap(
+        short,
+        long,
+        help = "Number of repositories handled at the same time, default 8",
+        env = "GITBUCKET_JOBS"
+    )]
+    jobs: Option<usize>,
+    #[clap(
+        long,
+        help = "Number of retries of transient clone, fetch and REST failures, default 3",
+        env = "GITBUCKET_RETRIES"
+    )]
+    retries: Option<u32>,
+    #[clap(
+        long,
+        name = "MILLISECONDS",
+        help = "Delay before the first retry, doubled for every next retry, default 500",
+        env = "GITBUCKET_RETRY_BACKOFF"
+    )]
+    retry_backoff: Option<u64>,
+    #[clap(
+        long,
+        possible_values = &["ssh", "https", "auto"],
+        help = "Protocol used to clone new repositories, default ssh",
+        env = "GITBUCKET_CLONE_PROTOCOL"
+    )]
+    clone_protocol: Option<CloneProtocol>,
+    #[clap(
+        long,
+        possible_values = &["skip", "separate"],
+        help = "Skip archived repositories or clone them into _archived/PROJECT, default skip",
+        env = "GITBUCKET_ARCHIVED"
+    )]
+    archived: Option<ArchivedRepositories>,
+    #[clap(
+        long,
+        arg_enum,
+        default_value_t,
+        help = "Format of the results on stdout, logging goes to stderr for json and ndjson",
+        env = "GITBUCKET_OUTPUT"
+    )]
+    output: OutputFormat,
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "error",
+        help = "Repository outcomes that give a failing exit code next to failed repositories",
+        env = "GITBUCKET_FAIL_ON",
+        multiple_occurrences = true,
+        value_delimiter = ','
+    )]
+    fail_on: Vec<FailOn>,
 }
 
 impl CliArgs {
-    fn default_private_key() -> errors::Result<String> {
-        let home_dir_location =
-            env::var("HOME").map_err(errors::Error::HOMEEnvironmentVariableNotFound)?;
-        Ok(format!("{}/.ssh/id_rsa", home_dir_location))
+    /// The settings given on the commandline or in the environment
+    fn profile(&self) -> Profile {
+        Profile {
+            directory: self.directory.clone(),
+            private_key: self.private_key.clone(),
+            host_key_checking: self.host_key_checking,
+            known_hosts: self.known_hosts.clone(),
+            ca_file: self.ca_file.clone(),
+            client_certificate: self.client_certificate.clone(),
+            client_key: self.client_key.clone(),
+            proxy: self.proxy.clone(),
+            no_proxy: self.no_proxy.as_deref().map(split_patterns),
+            timeout: self.timeout,
+            included_projects: self.included_projects.as_deref().map(split_patterns),
+            excluded_projects: self.excluded_projects.as_deref().map(split_patterns),
+            clone_protocol: self.clone_protocol,
+            archived: self.archived,
+            main_branches: self.main_branches.as_deref().map(split_patterns),
+            jobs: self.jobs,
+            retries: self.retries,
+            retry_backoff: self.retry_backoff,
+            inventory_ttl: self.inventory_ttl,
+            ..Profile::default()
+        }
+    }
+}
+
+/// Split comma-separated patterns
+fn split_patterns(patterns: &str) -> Vec<String> {
+    patterns.split(',').map(|p| p.trim().to_owned()).collect()
+}
+
+/// The arguments to connect to the hosting provider and select its repositories
+#[derive(Debug, Args)]
+struct ConnectionArgs {
+    #[clap(short, long, help = "Bitbucket user", env = "GITBUCKET_USER")]
+    user: Option<String>,
+    #[clap(long, help = "Bitbucket password", required = false)]
+    password: Option<String>,
+    #[clap(
+        long,
+        help = "Bitbucket HTTP access token",
+        required = false,
+        env = "GITBUCKET_TOKEN",
+        hide_env_values = true,
+        conflicts_with_all = &["password", "token-file"]
+    )]
+    token: Option<String>,
+    #[clap(
+        long,
+        help = "File containing a Bitbucket HTTP access token",
+        required = false,
+        env = "GITBUCKET_TOKEN_FILE",
+        conflicts_with = "password"
+    )]
+    token_file: Option<String>,
+    #[clap(
+        long,
+        arg_enum,
+        help = "Hosting provider, default bitbucket",
+        env = "GITBUCKET_PROVIDER"
+    )]
+    provider: Option<ProviderKind>,
+    #[clap(
+        long,
+        alias = "root-url",
+        help = "Bitbucket root url, or the (API) root url of the hosting provider",
+        required = false,
+        env = "GITBUCKET_ROOT_URL"
+    )]
+    bitbucket_root_url: Option<String>,
+    #[clap(
+        long,
+        help = "Bitbucket Cloud workspace, mirrors bitbucket.org instead of a Bitbucket Server",
+        required = false,
+        env = "GITBUCKET_WORKSPACE"
+    )]
+    workspace: Option<String>,
+    #[clap(
+        long = "organization",
+        help = "GitHub/Gitea organizations or GitLab groups",
+        required = false,
+        env = "GITBUCKET_ORGANIZATIONS",
+        value_delimiter = ','
+    )]
+    organizations: Option<Vec<String>>,
+    #[clap(
+        long,
+        name = "USERS",
+        help = "Include personal repositories of the given users, or of the current user when none are given",
+        required = false,
+        env = "GITBUCKET_INCLUDE_PERSONAL",
+        min_values = 0,
+        value_delimiter = ','
+    )]
+    include_personal: Option<Vec<String>>,
+    #[clap(
+        long,
+        help = "Directory for personal repositories, {user} is replaced by the owner, default ~{user}",
+        env = "GITBUCKET_PERSONAL_DIRECTORY"
+    )]
+    personal_directory: Option<String>,
+}
+
+impl ConnectionArgs {
+    /// The settings given on the commandline or in the environment
+    fn profile(&self) -> Profile {
+        Profile {
+            provider: self.provider,
+            root_url: self.bitbucket_root_url.clone(),
+            workspace: self.workspace.clone(),
+            organizations: self.organizations.clone(),
+            user: self.user.clone(),
+            password: self.password.clone(),
+            token: self.token.clone(),
+            token_file: self.token_file.clone(),
+            include_personal: self.include_personal.clone(),
+            personal_directory: self.personal_directory.clone(),
+            ..Profile::default()
+        }
     }
 }
 
@@ -118,34 +772,84 @@ impl CliArgs {
 enum CliCommands {
     #[clap(about = "clone new repositories")]
     Clone {
+        #[clap(flatten)]
+        connection: ConnectionArgs,
+    },
+    #[clap(about = "list the repositories on the server and whether they are cloned")]
+    List {
+        #[clap(flatten)]
+        connection: ConnectionArgs,
+        #[clap(
+            long,
+            help = "only list the repositories in these projects",
+            multiple_occurrences = true,
+            value_delimiter = ','
+        )]
+        project: Vec<String>,
+        #[clap(long, help = "leave out the repositories excluded from cloning")]
+        selected: bool,
         #[clap(
-            short,
             long,
-            help = "Bitbucket user",
-            required = true,
-            env = "GITBUCKET_USER"
+            help = "fetch the default branches missing from the listing, one request per repository"
         )]
-        user: String,
-        #[clap(long, help = "Bitbucket password", required = false)]
-        password: Option<String>,
+        default_branches: bool,
+    },
+    #[clap(about = "report, relocate or delete local repositories no longer on the server")]
+    Prune {
+        #[clap(flatten)]
+        connection: ConnectionArgs,
         #[clap(
             long,
-            help = "Bitbucket root url",
-            required = false,
-            env = "GITBUCKET_ROOT_URL"
+            help = "move repositories that moved to another project on the server"
         )]
-        bitbucket_root_url: String,
+        relocate: bool,
+        #[clap(long, help = "delete repositories that no longer exist on the server")]
+        delete: bool,
+    },
+    #[clap(about = "configuration file")]
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommands,
     },
-    #[clap(about = "show repositories not on main/master/develop")]
+    #[clap(about = "show repositories not on a main branch")]
     Featured {
-        #[clap(long, help = "show main/master/develop branches")]
+        #[clap(long, help = "show main branches")]
         show_main: bool,
+        #[clap(
+            long,
+            help = "take the default branch of every repository from the server instead of origin/HEAD"
+        )]
+        remote_default_branch: bool,
+        #[clap(flatten)]
+        connection: ConnectionArgs,
     },
     #[clap(about = "pull and update clean repositories")]
     Pull {
         #[clap(long, help = "show errors")]
         show_errors: bool,
+        #[clap(flatten)]
+        connection: ConnectionArgs,
     },
     #[clap(about = "status from repositories")]
     Status,
 }
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommands {
+    #[clap(about = "show the effective configuration")]
+    Show {
+        #[clap(flatten)]
+        connection: ConnectionArgs,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn valid_arguments() {
+        Cli::command().debug_assert();
+    }
+}
@@ -7,6 +7,48 @@ pub enum Error {
     // CLI error
     #[error("HOME environment variable not found")]
     HOMEEnvironmentVariableNotFound(#[source] std::env::VarError),
+    #[error("Reading token file {file}")]
+    FailedToReadTokenFile {
+        file: String,
+        source: std::io::Error,
+    },
+    #[error("token file {0} is empty")]
+    EmptyTokenFile(String),
+    #[error("no user given")]
+    MissingUser,
+    #[error("no password given and none could be prompted for")]
+    FailedToPromptPassword(#[source] std::io::Error),
+    #[error("no root url given for provider {0}")]
+    MissingRootUrl(String),
+    #[error("no organization given for provider {0}")]
+    MissingOrganization(String),
+
+    // Config errors
+    #[error("Reading configuration file {file}")]
+    FailedToReadConfig {
+        file: String,
+        source: std::io::Error,
+    },
+    #[error("Parsing configuration file {file}")]
+    FailedToParseConfig {
+        file: String,
+        source: toml::de::Error,
+    },
+    #[error("profile {0} not found in configuration file")]
+    UnknownProfile(String),
+    #[error("invalid {setting} {value}")]
+    InvalidSetting {
+        setting: &'static str,
+        value: String,
+    },
+    #[error("invalid pattern {pattern}")]
+    InvalidPattern {
+        pattern: String,
+        source: regex::Error,
+    },
+
+    #[error("unexpected failure: {0}")]
+    Panicked(String),
 
     // Git errors
     #[error("General git2 error")]
@@ -15,14 +57,54 @@ pub enum Error {
     NoBranchFound,
     #[error("no branchname found")]
     NoBranchnameFound,
+    #[error("host key of {host} not found in known_hosts, fingerprint {fingerprint}")]
+    UnknownHostKey { host: String, fingerprint: String },
+    #[error("host key of {host} does not match known_hosts, fingerprint {fingerprint}")]
+    HostKeyMismatch { host: String, fingerprint: String },
+    #[error("host key of {host} is revoked in known_hosts, fingerprint {fingerprint}")]
+    RevokedHostKey { host: String, fingerprint: String },
+    #[error("no SHA-256 or SHA-1 host key hash of {0}, unable to check it")]
+    MissingHostKeyHash(String),
 
     // IO errors
+    #[error("Reading known hosts file {file}")]
+    FailedToReadKnownHosts {
+        file: String,
+        source: std::io::Error,
+    },
     #[error("Reading directory {directory}")]
     FailedToReadDirectory {
         directory: String,
         source: std::io::Error,
     },
 
+    #[error("Pruning directory {directory}")]
+    FailedToPrune {
+        directory: String,
+        source: std::io::Error,
+    },
+    #[error("Reading inventory file {file}")]
+    FailedToReadInventory {
+        file: String,
+        source: std::io::Error,
+    },
+    #[error("Parsing inventory file {file}")]
+    FailedToParseInventory {
+        file: String,
+        source: serde_json::Error,
+    },
+    #[error("Writing inventory file {file}")]
+    FailedToWriteInventory {
+        file: String,
+        source: std::io::Error,
+    },
+    #[error("no inventory in {0}, run once without --offline")]
+    MissingInventory(String),
+    #[error("the inventory is of {listed}, not of {expected}, run once without --offline")]
+    InventoryOfOtherSource { listed: String, expected: String },
+    #[error("prune --delete needs a listing of the server, not the inventory of --offline")]
+    OfflineDelete,
+
     // REST errors
     /// A general error from the Ureq library
     #[error("general ureq error")]
@@ -36,4 +118,91 @@ pub enum Error {
     /// SSH URL could not be found
     #[error("ssh url not found")]
     SshUrlMissing,
+    /// HTTP(S) URL could not be found
+    #[error("http url not found")]
+    HttpUrlMissing,
+
+    // Network errors
+    #[error("Reading certificate file {file}")]
+    FailedToReadCertificate {
+        file: String,
+        source: std::io::Error,
+    },
+    #[error("invalid certificate or private key in {file}")]
+    InvalidCertificate { file: String },
+    #[error("a client certificate is only supported by the REST API, not by git over https, use clone protocol ssh")]
+    ClientCertificateOverHttps,
+    #[error("invalid client certificate")]
+    InvalidClientCertificate(#[source] rustls::Error),
+    #[error("invalid proxy {proxy}")]
+    InvalidProxy {
+        proxy: String,
+        source: Box<ureq::Error>,
+    },
+}
+
+impl Error {
+    /// Whether the error is caused by the configuration, the commandline or the environment
+    pub fn is_configuration(&self) -> bool {
+        matches!(
+            self,
+            Error::HOMEEnvironmentVariableNotFound(_)
+                | Error::FailedToReadTokenFile { .. }
+                | Error::EmptyTokenFile(_)
+                | Error::MissingUser
+                | Error::FailedToPromptPassword(_)
+                | Error::MissingRootUrl(_)
+                | Error::MissingOrganization(_)
+                | Error::FailedToReadConfig { .. }
+                | Error::FailedToParseConfig { .. }
+                | Error::UnknownProfile(_)
+                | Error::InvalidSetting { .. }
+                | Error::InvalidPattern { .. }
+                | Error::FailedToReadKnownHosts { .. }
+                | Error::FailedToReadCertificate { .. }
+                | Error::InvalidCertificate { .. }
+                | Error::InvalidClientCertificate(_)
+                | Error::ClientCertificateOverHttps
+                | Error::InvalidProxy { .. }
+                | Error::MissingInventory(_)
+                | Error::InventoryOfOtherSource { .. }
+                | Error::OfflineDelete
+        )
+    }
+
+    /// Whether retrying may succeed, e.g. after a network failure or an overloaded server
+    pub fn is_transient(&self) -> bool {
+        match self {
+            // Refused or reset connections are reported as OS errors
+            Error::FailedGitOperation(e) => {
+                !matches!(
+                    e.code(),
+                    git2::ErrorCode::Auth | git2::ErrorCode::Certificate
+                ) && matches!(
+                    e.class(),
+                    git2::ErrorClass::Net
+                        | git2::ErrorClass::Os
+                        | git2::ErrorClass::Ssh
+                        | git2::ErrorClass::Http
+                )
+            }
+            Error::FailedToDoHttpCall(e) => match e.as_ref() {
+                ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+                ureq::Error::Transport(transport) => matches!(
+                    transport.kind(),
+                    ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+                ),
+            },
+            _ => false,
+        }
+    }
+
+    /// Whether the server rejected the credentials
+    pub fn is_authentication(&self) -> bool {
+        match self {
+            Error::InvalidUsernamePassword => true,
+            Error::FailedGitOperation(e) => e.code() == git2::ErrorCode::Auth,
+            _ => false,
+        }
+    }
 }
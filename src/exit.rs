@@ -0,0 +1,162 @@
+//! This module contains the exit codes of the process, so scripts and CI jobs can detect failures.
+
+use clap::ArgEnum;
+use gitbucket::{
+    errors,
+    git::report::{Outcome, Report},
+};
+
+/// Every repository succeeded
+pub const SUCCESS: u8 = 0;
+/// The command failed as a whole
+pub const FAILURE: u8 = 1;
+/// Invalid configuration, commandline arguments or environment, like clap usage errors
+pub const CONFIGURATION: u8 = 2;
+/// Some repositories failed, or matched the `--fail-on` policy
+pub const PARTIAL_FAILURE: u8 = 3;
+/// The server rejected the credentials
+pub const AUTHENTICATION: u8 = 4;
+
+/// The repository outcomes that make the process fail, next to the failed repositories
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailOn {
+    /// Repositories with changes in their workspace
+    Dirty,
+    /// Repositories whose local and remote branch diverged
+    Diverged,
+    /// Repositories for which the command failed, these always fail the process
+    Error,
+    /// Repositories that became archived on the server, outside the archived directory
+    Archived,
+}
+
+impl FailOn {
+    fn matches(self, outcome: &Outcome) -> bool {
+        match self {
+            FailOn::Dirty => matches!(outcome, Outcome::Dirty | Outcome::SkippedDirty),
+            FailOn::Diverged => matches!(outcome, Outcome::SkippedDiverged),
+            FailOn::Error => matches!(outcome, Outcome::Failed(_)),
+            FailOn::Archived => matches!(
+                outcome,
+                Outcome::Archived {
+                    separate: false,
+                    ..
+                }
+            ),
+        }
+    }
+}
+
+/// The exit code for the report of a command, failed repositories always fail it and `fail_on`
+/// adds other outcomes
+pub fn report_code(report: &Report, fail_on: &[FailOn]) -> u8 {
+    let failing = || {
+        report.repositories.iter().filter(|repository| {
+            FailOn::Error.matches(&repository.outcome)
+                || fail_on.iter().any(|f| f.matches(&repository.outcome))
+        })
+    };
+    if report.failures().any(|repository| {
+        repository
+            .outcome
+            .error()
+            .map_or(false, errors::Error::is_authentication)
+    }) {
+        AUTHENTICATION
+    } else if failing().next().is_some() {
+        PARTIAL_FAILURE
+    } else {
+        SUCCESS
+    }
+}
+
+/// The exit code for an error that failed the command as a whole
+pub fn error_code(error: &eyre::Report) -> u8 {
+    match error.downcast_ref::<errors::Error>() {
+        Some(error) if error.is_configuration() => CONFIGURATION,
+        Some(error) if error.is_authentication() => AUTHENTICATION,
+        _ => FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use std::time::Duration;
+
+    fn report(outcomes: Vec<Outcome>) -> Report {
+        fixtures::report(
+            outcomes
+                .into_iter()
+                .map(|outcome| fixtures::repository("/src/CORE/api", outcome))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn success_unless_failing() {
+        let report = report(vec![Outcome::FastForwarded, Outcome::SkippedDirty]);
+
+        assert_eq!(report_code(&report, &[FailOn::Error]), SUCCESS);
+        assert_eq!(report_code(&report, &[FailOn::Dirty]), PARTIAL_FAILURE);
+        assert_eq!(report_code(&report, &[FailOn::Diverged]), SUCCESS);
+    }
+
+    #[test]
+    fn partial_failure() {
+        let report = report(vec![
+            Outcome::UpToDate,
+            Outcome::Failed(errors::Error::NoBranchFound),
+        ]);
+
+        assert_eq!(report_code(&report, &[FailOn::Error]), PARTIAL_FAILURE);
+        assert_eq!(report_code(&report, &[FailOn::Dirty]), PARTIAL_FAILURE);
+        assert_eq!(report_code(&report, &[]), PARTIAL_FAILURE);
+    }
+
+    #[test]
+    fn authentication_failure() {
+        let report = report(vec![
+            Outcome::Failed(errors::Error::NoBranchFound),
+            Outcome::Failed(errors::Error::InvalidUsernamePassword),
+        ]);
+
+        assert_eq!(report_code(&report, &[FailOn::Error]), AUTHENTICATION);
+        assert_eq!(report_code(&report, &[FailOn::Diverged]), AUTHENTICATION);
+        assert_eq!(
+            error_code(&eyre::Report::new(errors::Error::InvalidUsernamePassword)),
+            AUTHENTICATION
+        );
+    }
+
+    #[test]
+    fn configuration_error() {
+        let error = eyre::Report::new(errors::Error::UnknownProfile("customer".to_owned()));
+
+        assert_eq!(error_code(&error), CONFIGURATION);
+        assert_eq!(
+            error_code(&eyre::Report::new(errors::Error::SshUrlMissing)),
+            FAILURE
+        );
+    }
+
+    #[test]
+    fn fail_on_archived() {
+        let separate = report(vec![Outcome::Archived {
+            separate: true,
+            listed_ago: None,
+        }]);
+        let became_archived = report(vec![Outcome::Archived {
+            separate: false,
+            listed_ago: Some(Duration::from_secs(60)),
+        }]);
+
+        assert_eq!(report_code(&separate, &[FailOn::Archived]), SUCCESS);
+        assert_eq!(
+            report_code(&became_archived, &[FailOn::Archived]),
+            PARTIAL_FAILURE
+        );
+        assert_eq!(report_code(&became_archived, &[FailOn::Error]), SUCCESS);
+    }
+}
@@ -0,0 +1,33 @@
+//! Reports to test the rendering and the exit code of reports with
+
+use gitbucket::git::report::{Outcome, Report, RepositoryReport};
+use std::{path::Path, time::Duration};
+
+/// The report of the repository at a path `/src/PROJECT/repository`, clean on branch main
+/// after 42ms
+pub fn repository(path: &str, outcome: Outcome) -> RepositoryReport {
+    let path = Path::new(path);
+    let name = |path: Option<&Path>| {
+        path.and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    RepositoryReport {
+        path: path.to_path_buf(),
+        project: name(path.parent()),
+        repository: name(Some(path)),
+        branch: Some("main".to_owned()),
+        clean: Some(true),
+        outcome,
+        duration: Duration::from_millis(42),
+    }
+}
+
+/// The report of a command on the repositories after 1234ms
+pub fn report(repositories: Vec<RepositoryReport>) -> Report {
+    Report {
+        dry_run: false,
+        repositories,
+        duration: Duration::from_millis(1234),
+    }
+}
@@ -0,0 +1,78 @@
+use regex::Regex;
+
+use crate::{
+    errors,
+    git::exclusions::{compile_pattern, glob_to_regex},
+};
+
+/// The mainline branches used when none are configured
+pub const DEFAULT_MAIN_BRANCHES: [&str; 3] = ["main", "master", "develop"];
+
+/// Glob or `re:` patterns for the mainline branches, all other branches are feature branches
+#[derive(Clone, Debug)]
+pub struct MainBranches {
+    patterns: Vec<Regex>,
+}
+
+impl Default for MainBranches {
+    fn default() -> Self {
+        let patterns = DEFAULT_MAIN_BRANCHES.map(String::from);
+        MainBranches::new(&patterns).unwrap()
+    }
+}
+
+impl MainBranches {
+    pub fn new(patterns: &[String]) -> errors::Result<MainBranches> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| pattern.trim())
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| compile_pattern(pattern, glob_to_regex))
+            .collect::<errors::Result<Vec<Regex>>>()?;
+
+        Ok(MainBranches { patterns })
+    }
+
+    /// Whether the branch is a mainline branch, the default branch of a repository always is
+    pub fn is_main(&self, branch: &str, default_branch: Option<&str>) -> bool {
+        default_branch == Some(branch) || self.patterns.iter().any(|p| p.is_match(branch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_main_branches() {
+        let main_branches = MainBranches::default();
+
+        assert!(main_branches.is_main("master", None));
+        assert!(!main_branches.is_main("feature/login", None));
+        assert!(main_branches.is_main("trunk", Some("trunk")));
+    }
+
+    #[test]
+    fn patterns() {
+        let main_branches =
+            MainBranches::new(&["trunk".to_owned(), "release/*".to_owned()]).unwrap();
+
+        assert!(main_branches.is_main("trunk", None));
+        assert!(main_branches.is_main("release/1.2", None));
+        assert!(!main_branches.is_main("release/1.2/hotfix", None));
+        assert!(!main_branches.is_main("main", None));
+    }
+
+    #[test]
+    fn regular_expressions() {
+        let main_branches = MainBranches::new(&[r"re:^release-\d+$".to_owned()]).unwrap();
+
+        assert!(main_branches.is_main("release-12", None));
+        assert!(!main_branches.is_main("release-12-rc", None));
+        assert!(!main_branches.is_main("re:^release-\\d+$", None));
+        assert!(matches!(
+            MainBranches::new(&["re:(".to_owned()]),
+            Err(errors::Error::InvalidPattern { .. })
+        ));
+    }
+}
@@ -1,82 +1,196 @@
-use std::path::Path;
+use std::{
+    iter,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 use git2::build::RepoBuilder;
-use tracing::{error, info, trace};
+use indicatif::ProgressBar;
+use tracing::{trace, warn};
 
 use crate::{
-    bitbucket::{all_repositories, BitbucketCredentials},
+    bitbucket::BitbucketCredentials,
     errors,
-    git::{exclusions::Exclusions, BitbucketRepository, Git},
+    git::{
+        personal::PersonalRepositories,
+        report::{Exclusion, Outcome, PendingReport, Report},
+        server_project, ArchivedRepositories, BitbucketRepository, Git,
+    },
+    provider::Provider,
 };
 
+/// The path reported for a page of the listing that keeps failing
+const LISTING: &str = "listing";
+
 pub async fn run(
     git: Git,
-    bitbucket_root_url: &str,
+    provider: &dyn Provider,
     credentials: &BitbucketCredentials,
-    exclusions: Exclusions,
-) -> errors::Result<()> {
-    let repositories = all_repositories(bitbucket_root_url, credentials, 250)?
-        .into_iter()
-        .filter(|(project, _)| exclusions.excludes_project(project))
-        .flat_map(|(project, repositories)| flatten_repositories(project, repositories))
-        .filter(|(project, repository)| exclusions.excludes_repository(project, repository))
-        .collect::<Vec<(String, BitbucketRepository)>>();
-
-    std::thread::scope(|s| {
-        repositories.into_iter().for_each(|(project, repository)| {
-            let project_string = format!("{}/{}", git.root_directory, project);
-            s.spawn(|| {
-                let span = tracing::info_span!(
-                    "clone_repository",
-                    "{}/{}",
-                    project_string,
-                    repository.name
-                );
-                if let Err(e) = span.in_scope(|| clone_repository(&git, project_string, repository))
-                {
-                    error!("Error {:?}", e);
+    personal: PersonalRepositories,
+) -> errors::Result<Report> {
+    let start = Instant::now();
+
+    // Cloning starts with the first page, while the next pages are fetched when needed
+    let overall = git.progress.overall(0);
+    let reports = git.for_each_iter(
+        git.repositories(provider, credentials, false),
+        |repository| {
+            // The repositories cloned so far are reported, with the listing as failed
+            let (project, repository) = match repository {
+                Ok(repository) => repository,
+                Err(e) => {
+                    let path = Path::new(&git.root_directory).join(LISTING);
+                    return PendingReport::new(&git, &path).finish(Err(e));
+                }
+            };
+            let directory = match select_repository(&git, &personal, &project, &repository) {
+                Ok(directory) => directory,
+                Err(exclusion) => {
+                    let path = repository_path(&git, &project, &repository);
+                    let outcome = Outcome::Excluded(exclusion);
+                    return PendingReport::new(&git, &path).finish(Ok(outcome));
                 }
-            });
-        });
-    });
+            };
 
-    Ok(())
+            overall.inc_length(1);
+            let path = local_path(&git, &directory, &repository);
+            let span = tracing::info_span!("clone_repository", "{}", path.to_string_lossy());
+            let report = PendingReport::new(&git, &path)
+                .run(|_| span.in_scope(|| clone_repository(&git, credentials, &path, &repository)));
+            overall.inc(1);
+            report
+        },
+    );
+    overall.finish_and_clear();
+
+    Ok(Report::new(&git, reports, start))
 }
 
-fn flatten_repositories(
-    project: String,
-    repositories: Vec<BitbucketRepository>,
-) -> impl Iterator<Item = (String, BitbucketRepository)> {
-    repositories.into_iter().map(move |r| (project.clone(), r))
+/// The local path of a repository in a project directory, a clone made under its name when
+/// there is one and none under its slug
+pub(super) fn repository_path(
+    git: &Git,
+    project: &str,
+    repository: &BitbucketRepository,
+) -> PathBuf {
+    let directory = Path::new(&git.root_directory).join(project);
+    let path = directory.join(repository.directory_name());
+    let named = directory.join(&repository.name);
+    match !path.exists() && named.exists() {
+        true => named,
+        false => path,
+    }
+}
+
+/// The local path of a selected repository, where it was cloned before it was archived when
+/// that clone still exists, until prune relocates it
+pub(super) fn local_path(git: &Git, directory: &str, repository: &BitbucketRepository) -> PathBuf {
+    let path = repository_path(git, directory, repository);
+    match server_project(directory) {
+        (project, true) if !path.exists() => {
+            let unarchived = repository_path(git, project, repository);
+            match unarchived.exists() {
+                true => unarchived,
+                false => path,
+            }
+        }
+        _ => path,
+    }
+}
+
+/// Determine the project directory of a selected repository, or why it is not selected
+pub(super) fn select_repository(
+    git: &Git,
+    personal: &PersonalRepositories,
+    project: &str,
+    repository: &BitbucketRepository,
+) -> Result<String, Exclusion> {
+    let directory = match repository.owner {
+        None => project.to_owned(),
+        Some(_) => personal
+            .project_directory(repository)
+            .ok_or(Exclusion::Personal)?,
+    };
+    let names = iter::once(repository.name.as_str())
+        .chain(repository.slug.as_deref().filter(|slug| !slug.is_empty()))
+        .collect::<Vec<_>>();
+    if git.exclusions.is_excluded_by_names(&directory, &names) {
+        return Err(Exclusion::Selection);
+    }
+    if repository.archived && git.archived == ArchivedRepositories::Skip {
+        return Err(Exclusion::Archived);
+    }
+
+    Ok(git.project_directory(directory, repository))
 }
 
 fn clone_repository(
     git: &Git,
-    project: String,
-    repository: BitbucketRepository,
-) -> errors::Result<()> {
-    let repo_string = format!("{}/{}", project, repository.name);
-    let repo_path = Path::new(&repo_string);
+    credentials: &BitbucketCredentials,
+    repo_path: &Path,
+    repository: &BitbucketRepository,
+) -> errors::Result<Outcome> {
     trace!("Checking repository");
-    if !repo_path.exists() {
-        info!("Cloning repository from {}", &repository.git_url);
-        if !git.dry_run {
-            do_clone(git, repo_path, &repository.git_url)?;
-        }
+    if repo_path.exists() {
+        return Ok(Outcome::Existing);
     }
 
-    Ok(())
+    let urls = git.clone_protocol.urls(repository)?;
+    let url = match git.dry_run {
+        true => urls[0],
+        false => {
+            let (project, name) = git.project_and_name(repo_path);
+            let bar = git.progress.repository(&format!("{}/{}", project, name));
+            let result = do_clone(git, credentials, repo_path, &urls, &bar);
+            bar.finish_and_clear();
+            result?
+        }
+    };
+
+    Ok(Outcome::Cloned {
+        url: url.to_owned(),
+    })
 }
 
-fn do_clone(git: &Git, repo_path: &Path, git_url: &str) -> errors::Result<()> {
-    let fo = git.fetch_options();
+/// Clone from the first url that succeeds, returning that url
+fn do_clone<'a>(
+    git: &Git,
+    credentials: &BitbucketCredentials,
+    repo_path: &Path,
+    git_urls: &[&'a str],
+    bar: &ProgressBar,
+) -> errors::Result<&'a str> {
+    let (last_url, fallback_urls) = git_urls.split_last().ok_or(errors::Error::SshUrlMissing)?;
+    for git_url in fallback_urls {
+        match git
+            .retry
+            .run(|| clone_from(git, credentials, repo_path, git_url, bar))
+        {
+            Ok(()) => return Ok(git_url),
+            Err(e) => warn!("Cloning from {} failed, falling back - {}", git_url, e),
+        }
+    }
+
+    git.retry
+        .run(|| clone_from(git, credentials, repo_path, last_url, bar))?;
+    Ok(last_url)
+}
 
-    // Prepare builder.
-    let mut builder = RepoBuilder::new();
-    builder.fetch_options(fo);
+fn clone_from(
+    git: &Git,
+    credentials: &BitbucketCredentials,
+    repo_path: &Path,
+    git_url: &str,
+    bar: &ProgressBar,
+) -> errors::Result<()> {
+    git.fetch(git_url, Some(credentials), bar, |fo| {
+        // Prepare builder.
+        let mut builder = RepoBuilder::new();
+        builder.fetch_options(fo);
 
-    // Clone the project.
-    builder.clone(git_url, repo_path)?;
+        // Clone the project.
+        builder.clone(git_url, repo_path)
+    })?;
 
     Ok(())
 }
@@ -1,37 +1,232 @@
-use crate::bitbucket::BitbucketRepository;
+use regex::Regex;
 
-#[derive(Debug)]
+use crate::errors;
+
+/// The prefix marking a pattern as a regular expression instead of a glob
+const REGEX_PREFIX: &str = "re:";
+
+/// Ordered inclusion and exclusion rules for `PROJECT/repository` paths.
+///
+/// Patterns are globs (`*` and `?` within a path segment, `**` across segments) or regular
+/// expressions prefixed with `re:`. A glob without a `/` matches a whole project. When there are
+/// inclusions, only matching repositories are selected. Exclusions are applied in order, the last
+/// matching one wins and a `!` prefix re-includes what an earlier exclusion excluded.
+/// A subset selected with `only` narrows the selection further.
+#[derive(Clone, Debug, Default)]
 pub struct Exclusions {
-    exclusions: Vec<String>,
+    inclusions: Vec<Regex>,
+    exclusions: Vec<Rule>,
+    only: Vec<Regex>,
 }
 
-impl From<Option<String>> for Exclusions {
-    fn from(excluded_projects: Option<String>) -> Self {
-        let exclusions = excluded_projects.map_or_else(Vec::new, |val| {
-            val.split(',')
-                .map(Self::add_wildcard)
-                .collect::<Vec<String>>()
-        });
-
-        Exclusions { exclusions }
-    }
+#[derive(Clone, Debug)]
+struct Rule {
+    pattern: Regex,
+    negated: bool,
 }
 
 impl Exclusions {
-    pub fn excludes_project(&self, project: &str) -> bool {
-        !self.exclusions.contains(&format!("{}/*", project))
+    pub fn new(inclusions: &[String], exclusions: &[String]) -> errors::Result<Exclusions> {
+        let inclusions = Self::compile_all(inclusions)?;
+        let exclusions = exclusions
+            .iter()
+            .filter(|pattern| !pattern.trim().is_empty())
+            .map(|pattern| {
+                let pattern = pattern.trim();
+                let (pattern, negated) = match pattern.strip_prefix('!') {
+                    Some(pattern) => (pattern, true),
+                    None => (pattern, false),
+                };
+                Ok(Rule {
+                    pattern: Self::compile(pattern)?,
+                    negated,
+                })
+            })
+            .collect::<errors::Result<Vec<Rule>>>()?;
+
+        Ok(Exclusions {
+            inclusions,
+            exclusions,
+            only: Vec::new(),
+        })
+    }
+
+    /// Only select the repositories matching one of these patterns, e.g. `PROJECT` or
+    /// `PROJECT/repo`
+    pub fn only(self, only: &[String]) -> errors::Result<Exclusions> {
+        Ok(Exclusions {
+            only: Self::compile_all(only)?,
+            ..self
+        })
+    }
+
+    /// Whether the repository in the project is not selected by these rules
+    pub fn is_excluded(&self, project: &str, repository: &str) -> bool {
+        self.is_excluded_by_names(project, &[repository])
+    }
+
+    /// Whether the repository in the project is not selected by these rules, a pattern matching
+    /// it by any of its names, e.g. its name and its slug
+    pub fn is_excluded_by_names(&self, project: &str, names: &[&str]) -> bool {
+        let paths = names
+            .iter()
+            .map(|name| format!("{}/{}", project, name))
+            .collect::<Vec<_>>();
+        let matches = |pattern: &Regex| paths.iter().any(|path| pattern.is_match(path));
+        let selected = |patterns: &[Regex]| patterns.is_empty() || patterns.iter().any(&matches);
+        if !selected(&self.inclusions) || !selected(&self.only) {
+            return true;
+        }
+
+        self.exclusions
+            .iter()
+            .rev()
+            .find(|rule| matches(&rule.pattern))
+            .map_or(false, |rule| !rule.negated)
     }
 
-    pub fn excludes_repository(&self, project: &str, repository: &BitbucketRepository) -> bool {
-        !self
-            .exclusions
-            .contains(&format!("{}/{}", project, repository.name))
+    fn compile_all(patterns: &[String]) -> errors::Result<Vec<Regex>> {
+        patterns
+            .iter()
+            .filter(|pattern| !pattern.trim().is_empty())
+            .map(|pattern| Self::compile(pattern))
+            .collect()
+    }
+
+    fn compile(pattern: &str) -> errors::Result<Regex> {
+        compile_pattern(pattern, |glob| glob_to_regex(&Self::add_wildcard(glob)))
     }
 
     fn add_wildcard(s: &str) -> String {
         match s.contains('/') {
-            true => s.trim().to_owned(),
-            false => format!("{}/*", s.trim()),
+            true => s.to_owned(),
+            false => format!("{}/*", s),
+        }
+    }
+}
+
+/// Compile a regular expression prefixed with `re:`, or else a glob translated by `glob`
+pub(crate) fn compile_pattern(
+    pattern: &str,
+    glob: impl Fn(&str) -> String,
+) -> errors::Result<Regex> {
+    let pattern = pattern.trim();
+    let regex = match pattern.strip_prefix(REGEX_PREFIX) {
+        Some(regex) => regex.to_owned(),
+        None => glob(pattern),
+    };
+
+    Regex::new(&regex).map_err(|source| errors::Error::InvalidPattern {
+        pattern: pattern.to_owned(),
+        source,
+    })
+}
+
+/// Translate a glob into an anchored regular expression, `*` and `?` match within a path segment
+/// and `**` also matches across segments
+pub(crate) fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
         }
     }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exclusions(inclusions: &[&str], exclusions: &[&str]) -> Exclusions {
+        let to_strings =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        Exclusions::new(&to_strings(inclusions), &to_strings(exclusions)).unwrap()
+    }
+
+    #[test]
+    fn exact_project_and_repository() {
+        let exclusions = exclusions(&[], &["ARCHIVE", "CORE/old"]);
+
+        assert!(exclusions.is_excluded("ARCHIVE", "anything"));
+        assert!(exclusions.is_excluded("CORE", "old"));
+        assert!(!exclusions.is_excluded("CORE", "new"));
+        assert!(!exclusions.is_excluded("ARCHIVE2", "anything"));
+    }
+
+    #[test]
+    fn globs() {
+        let exclusions = exclusions(&[], &["*-archive", "LEGACY*/*", "**/tmp-?"]);
+
+        assert!(exclusions.is_excluded("CORE-archive", "repo"));
+        assert!(exclusions.is_excluded("LEGACY2", "repo"));
+        assert!(exclusions.is_excluded("group/sub", "tmp-1"));
+        assert!(!exclusions.is_excluded("CORE", "repo-archive"));
+        assert!(!exclusions.is_excluded("group/sub", "tmp-12"));
+    }
+
+    #[test]
+    fn negation_last_match_wins() {
+        let exclusions = exclusions(&[], &["CORE", "!CORE/keep-me", "CORE/keep-me-not*"]);
+
+        assert!(exclusions.is_excluded("CORE", "other"));
+        assert!(!exclusions.is_excluded("CORE", "keep-me"));
+        assert!(exclusions.is_excluded("CORE", "keep-me-not-either"));
+    }
+
+    #[test]
+    fn inclusions() {
+        let exclusions = exclusions(&["PLATFORM", "TOOLS/cli-*"], &["PLATFORM/secret"]);
+
+        assert!(!exclusions.is_excluded("PLATFORM", "api"));
+        assert!(!exclusions.is_excluded("TOOLS", "cli-deploy"));
+        assert!(exclusions.is_excluded("TOOLS", "web"));
+        assert!(exclusions.is_excluded("PLATFORM", "secret"));
+    }
+
+    #[test]
+    fn only_subset() {
+        let exclusions = exclusions(&["PLATFORM", "TOOLS"], &["PLATFORM/secret"])
+            .only(&["PLATFORM".to_owned(), "CORE/api".to_owned()])
+            .unwrap();
+
+        assert!(!exclusions.is_excluded("PLATFORM", "api"));
+        assert!(exclusions.is_excluded("PLATFORM", "secret"));
+        assert!(exclusions.is_excluded("TOOLS", "cli"));
+        assert!(exclusions.is_excluded("CORE", "api"));
+    }
+
+    #[test]
+    fn name_or_slug() {
+        let exclusions = exclusions(&[], &["CORE/My Tool"])
+            .only(&["CORE/my-*".to_owned()])
+            .unwrap();
+
+        assert!(exclusions.is_excluded_by_names("CORE", &["My Tool", "my-tool"]));
+        assert!(!exclusions.is_excluded_by_names("CORE", &["My Api", "my-api"]));
+        assert!(exclusions.is_excluded("CORE", "My Api"));
+    }
+
+    #[test]
+    fn regular_expressions() {
+        let exclusions = exclusions(&[], &[r"re:^[A-Z]+/.*-(old|tmp)$"]);
+
+        assert!(exclusions.is_excluded("CORE", "api-old"));
+        assert!(!exclusions.is_excluded("core", "api-old"));
+    }
+
+    #[test]
+    fn invalid_regular_expression() {
+        let result = Exclusions::new(&[], &["re:(".to_owned()]);
+
+        assert!(matches!(result, Err(errors::Error::InvalidPattern { .. })));
+    }
 }
@@ -1,59 +1,178 @@
 use git2::{ErrorCode, Reference, Repository};
-use std::path::Path;
-use tracing::{error, info, trace, Instrument};
-
-use crate::{errors, git::Git};
-
-pub async fn run(git: Git, show_main: bool) -> errors::Result<()> {
-    for project in &git.get_existing_git_projects()? {
-        featured(project, show_main)
-            .instrument(tracing::info_span!(
-                "featured",
-                "{}",
-                project.to_string_lossy()
-            ))
-            .await?;
-    }
+use std::{collections::HashMap, path::Path, time::Instant};
+use tracing::{trace, warn};
+
+use crate::{
+    bitbucket::BitbucketCredentials,
+    errors,
+    git::{
+        head_branch, local_slug,
+        personal::PersonalRepositories,
+        report::{Outcome, PendingReport, Report},
+        server_project_key, Git,
+    },
+    provider::Provider,
+};
+
+pub async fn run(
+    git: Git,
+    remote: Option<(&dyn Provider, &BitbucketCredentials, &PersonalRepositories)>,
+) -> errors::Result<Report> {
+    let start = Instant::now();
+    let existing_projects = git.get_existing_git_projects()?;
+    let cached = match remote {
+        Some(_) => git.cached_default_branches()?,
+        None => HashMap::new(),
+    };
+    let remote =
+        remote.map(|(provider, credentials, personal)| (provider, credentials, &cached, personal));
+    let repositories = git.for_each(&existing_projects, |project| {
+        let span = tracing::info_span!("featured", "{}", project.to_string_lossy());
+        PendingReport::new(&git, project)
+            .run(|report| span.in_scope(|| featured(&git, project, remote, report)))
+    });
 
-    Ok(())
+    Ok(Report::new(&git, repositories, start))
 }
 
-async fn featured(directory: &Path, show_main: bool) -> errors::Result<()> {
+/// The provider with the default branches in the inventory and the personal directories
+type Remote<'a> = (
+    &'a dyn Provider,
+    &'a BitbucketCredentials,
+    &'a HashMap<(String, String), String>,
+    &'a PersonalRepositories,
+);
+
+fn featured(
+    git: &Git,
+    directory: &Path,
+    remote: Option<Remote<'_>>,
+    report: &mut PendingReport,
+) -> errors::Result<Outcome> {
     let repo = Repository::open(directory)?;
-    match repo.head() {
-        Ok(head) => debug_head(directory, &head, show_main).await?,
-        Err(e) => {
-            if e.code() != ErrorCode::UnbornBranch {
-                error!("Error in path: - {}", e);
-            }
+    let outcome = match repo.head() {
+        Ok(head) => {
+            let default_branch = default_branch(git, &repo, directory, remote);
+            report.branch = head_branch(&repo);
+            debug_head(git, directory, &head, default_branch)
         }
+        Err(e) if e.code() == ErrorCode::UnbornBranch => Outcome::SkippedDetached,
+        Err(e) => return Err(errors::Error::FailedGitOperation(e)),
     };
 
-    Ok(())
+    Ok(outcome)
+}
+
+/// The default branch from the inventory or the server when requested, or else from the local
+/// origin/HEAD
+fn default_branch(
+    git: &Git,
+    repo: &Repository,
+    directory: &Path,
+    remote: Option<Remote<'_>>,
+) -> Option<String> {
+    if let Some((provider, credentials, cached, personal)) = remote {
+        let (directory_project, _) = git.project_and_name(directory);
+        let project = server_project_key(&directory_project, personal);
+        let slug = local_slug(repo, directory);
+        if let Some(branch) = cached.get(&(project.to_lowercase(), slug.clone())) {
+            return Some(branch.to_owned());
+        }
+        if git.inventory.offline() {
+            trace!("No default branch in the inventory");
+        } else {
+            match git
+                .retry
+                .run(|| provider.default_branch(credentials, &project, &slug))
+            {
+                Ok(Some(branch)) => return Some(branch),
+                Ok(None) => trace!("No default branch on the server"),
+                Err(e) => warn!("Error fetching default branch - {}", e),
+            }
+        }
+    }
+
+    let origin_head = repo.find_reference("refs/remotes/origin/HEAD").ok()?;
+    origin_head
+        .symbolic_target()?
+        .strip_prefix("refs/remotes/origin/")
+        .map(str::to_owned)
 }
 
-async fn debug_head(directory: &Path, head: &Reference<'_>, show_main: bool) -> errors::Result<()> {
+fn debug_head(
+    git: &Git,
+    directory: &Path,
+    head: &Reference<'_>,
+    default_branch: Option<String>,
+) -> Outcome {
     trace!("Checking directory {}", directory.to_string_lossy());
 
-    if head.is_branch() {
-        match head.shorthand() {
-            Some(branchname) => match branchname {
-                "main" | "master" | "develop" => {
-                    if show_main {
-                        info!("on branch {}", branchname);
-                    }
-                }
-                _ => {
-                    info!("on branch {}", branchname);
-                }
-            },
-            None => {
-                error!("not on a branch");
+    match head.shorthand() {
+        Some(branchname) if head.is_branch() => {
+            match git
+                .main_branches
+                .is_main(branchname, default_branch.as_deref())
+            {
+                true => Outcome::Main,
+                false => Outcome::Featured { default_branch },
             }
         }
-    } else {
-        error!("not a branch");
+        _ => Outcome::SkippedDetached,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bitbucket::BitbucketSecret, provider::Pages};
+
+    /// A server with default branch trunk for the repositories in project CORE only
+    struct Server;
+
+    impl Provider for Server {
+        fn repository_pages<'a>(&'a self, _: &'a BitbucketCredentials) -> Pages<'a> {
+            Pages::new(Vec::new(), |_| unreachable!())
+        }
+
+        fn default_branch(
+            &self,
+            _: &BitbucketCredentials,
+            project: &str,
+            _: &str,
+        ) -> errors::Result<Option<String>> {
+            Ok((project == "CORE").then(|| "trunk".to_owned()))
+        }
     }
 
-    Ok(())
+    #[test]
+    fn default_branch_of_archived_and_personal_clones() {
+        let root = std::env::temp_dir().join(format!("gitbucket-featured-{}", std::process::id()));
+        let archived = root.join("_archived/CORE/api");
+        let personal_clone = root.join("~alice/notes");
+        let git = Git::builder()
+            .root_directory(root.to_string_lossy())
+            .dry_run(false)
+            .build();
+        let credentials = BitbucketCredentials::builder()
+            .username("alice")
+            .secret(BitbucketSecret::Password(String::new()))
+            .build();
+        let cached =
+            HashMap::from([(("~alice".to_owned(), "notes".to_owned()), "main".to_owned())]);
+        let personal = PersonalRepositories::new(None, "alice", "~{user}");
+        let remote = Some((&Server as &dyn Provider, &credentials, &cached, &personal));
+
+        let repo = Repository::init(&archived).unwrap();
+        assert_eq!(
+            default_branch(&git, &repo, &archived, remote),
+            Some("trunk".to_owned())
+        );
+        let repo = Repository::init(&personal_clone).unwrap();
+        assert_eq!(
+            default_branch(&git, &repo, &personal_clone, remote),
+            Some("main".to_owned())
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
 }
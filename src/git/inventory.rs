@@ -0,0 +1,317 @@
+//! This module contains the cache of the repositories listed by the provider
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, warn};
+use typed_builder::TypedBuilder;
+
+use crate::{
+    bitbucket::BitbucketRepository,
+    errors,
+    git::{report::error_message, repository_slug},
+};
+
+/// The file in the root directory holding the last listing of the provider
+pub const INVENTORY_FILE: &str = ".gitbucket-inventory.json";
+/// The default number of seconds the last listing is used instead of listing again
+pub const DEFAULT_INVENTORY_TTL_SECONDS: u64 = 600;
+
+/// When to use the last listing of the provider instead of listing the repositories again
+#[derive(TypedBuilder, Clone, Debug)]
+pub struct InventoryCache {
+    /// The provider and the workspace or organizations the listing is of
+    #[builder(default, setter(into))]
+    source: String,
+    /// How long the listing is used, zero to always list again
+    #[builder(default = Duration::from_secs(DEFAULT_INVENTORY_TTL_SECONDS))]
+    ttl: Duration,
+    /// Always use the listing, however old, and fail without one
+    #[builder(default)]
+    offline: bool,
+}
+
+impl Default for InventoryCache {
+    fn default() -> Self {
+        InventoryCache::builder().build()
+    }
+}
+
+impl InventoryCache {
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    /// The cached inventory to use instead of listing the provider, if any
+    pub(crate) fn cached(&self, root_directory: &Path) -> errors::Result<Option<Inventory>> {
+        let file = root_directory.join(INVENTORY_FILE);
+        let inventory = match Inventory::load(&file) {
+            Ok(inventory) => inventory,
+            Err(e) if !self.offline => {
+                warn!("Ignoring the inventory - {}", error_message(&e));
+                None
+            }
+            Err(e) => return Err(e),
+        };
+
+        match inventory {
+            Some(inventory) if self.offline => {
+                if inventory.source != self.source {
+                    return Err(errors::Error::InventoryOfOtherSource {
+                        listed: inventory.source,
+                        expected: self.source.clone(),
+                    });
+                }
+                debug!("Using the inventory of {}s ago", inventory.age().as_secs());
+                Ok(Some(inventory))
+            }
+            Some(inventory) if inventory.source == self.source && inventory.age() < self.ttl => {
+                debug!("Using the inventory of {}s ago", inventory.age().as_secs());
+                Ok(Some(inventory))
+            }
+            _ if self.offline => Err(errors::Error::MissingInventory(
+                root_directory.to_string_lossy().to_string(),
+            )),
+            _ => Ok(None),
+        }
+    }
+
+    /// The last inventory however old, None when there is none, it cannot be read or it is of
+    /// another source
+    pub(crate) fn last(&self, root_directory: &Path) -> Option<Inventory> {
+        let inventory =
+            Inventory::load(&root_directory.join(INVENTORY_FILE)).unwrap_or_else(|e| {
+                warn!("Ignoring the inventory - {}", error_message(&e));
+                None
+            })?;
+        if inventory.source != self.source {
+            warn!(
+                "Ignoring the inventory of {} instead of {}",
+                inventory.source, self.source
+            );
+            return None;
+        }
+        Some(inventory)
+    }
+
+    /// Store a complete listing of the provider, a failure only costs the next run a listing
+    pub(crate) fn save(
+        &self,
+        root_directory: &Path,
+        repositories: Vec<(String, BitbucketRepository)>,
+    ) {
+        let inventory = Inventory {
+            source: self.source.clone(),
+            listed_at: now(),
+            repositories: repositories
+                .into_iter()
+                .map(|(project, repository)| InventoryRepository {
+                    project,
+                    repository,
+                })
+                .collect(),
+        };
+        if let Err(e) = inventory.save(&root_directory.join(INVENTORY_FILE)) {
+            warn!("Not saving the inventory - {}", error_message(&e));
+        }
+    }
+}
+
+/// The repositories of the provider with their project, as listed at a moment
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Inventory {
+    source: String,
+    /// Seconds since the Unix epoch
+    listed_at: u64,
+    repositories: Vec<InventoryRepository>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct InventoryRepository {
+    project: String,
+    #[serde(flatten)]
+    repository: BitbucketRepository,
+}
+
+impl Inventory {
+    /// Read the inventory file, None when there is none
+    fn load(file: &Path) -> errors::Result<Option<Inventory>> {
+        let contents = match fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(errors::Error::FailedToReadInventory {
+                    file: file.to_string_lossy().to_string(),
+                    source,
+                })
+            }
+        };
+
+        serde_json::from_str(&contents).map(Some).map_err(|source| {
+            errors::Error::FailedToParseInventory {
+                file: file.to_string_lossy().to_string(),
+                source,
+            }
+        })
+    }
+
+    /// Write the inventory file through a temporary file, so a reader never sees half of it
+    fn save(&self, file: &Path) -> errors::Result<()> {
+        let write_error = |source| errors::Error::FailedToWriteInventory {
+            file: file.to_string_lossy().to_string(),
+            source,
+        };
+        let contents = serde_json::to_string_pretty(self).map_err(|e| write_error(e.into()))?;
+        let temporary = PathBuf::from(format!("{}.tmp", file.to_string_lossy()));
+        if let Some(directory) = file.parent() {
+            fs::create_dir_all(directory).map_err(write_error)?;
+        }
+        fs::write(&temporary, contents).map_err(write_error)?;
+        fs::rename(&temporary, file).map_err(write_error)
+    }
+
+    /// How long ago the repositories were listed
+    pub(crate) fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.listed_at))
+    }
+
+    /// The repositories with their project, in the order they were listed
+    pub(crate) fn repositories(self) -> impl Iterator<Item = (String, BitbucketRepository)> {
+        self.repositories
+            .into_iter()
+            .map(|repository| (repository.project, repository.repository))
+    }
+
+    /// The archived repositories in the listing, by project and repository slug
+    pub(crate) fn archived(&self) -> HashSet<(String, String)> {
+        self.repositories
+            .iter()
+            .filter(|cached| cached.repository.archived)
+            .filter_map(|cached| {
+                let slug = repository_slug(&cached.repository)?;
+                Some((cached.project.to_owned(), slug))
+            })
+            .collect()
+    }
+
+    /// The default branches in the listing, by lowercase project and repository slug
+    pub(crate) fn default_branches(&self) -> HashMap<(String, String), String> {
+        self.repositories
+            .iter()
+            .filter_map(|cached| {
+                let slug = repository_slug(&cached.repository)?;
+                let branch = cached.repository.default_branch.clone()?;
+                Some(((cached.project.to_lowercase(), slug), branch))
+            })
+            .collect()
+    }
+}
+
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_inventory_of_the_same_source() {
+        let directory = temporary_directory("fresh");
+        let cache = InventoryCache::builder().source("bitbucket").build();
+        cache.save(&directory, vec![("ATLAS".to_owned(), repository())]);
+
+        let inventory = cache.cached(&directory).unwrap().unwrap();
+        assert_eq!(
+            inventory
+                .default_branches()
+                .get(&("atlas".to_owned(), "core".to_owned())),
+            Some(&"trunk".to_owned())
+        );
+        assert_eq!(inventory.repositories().count(), 1);
+
+        let other = InventoryCache::builder().source("github").build();
+        assert!(other.cached(&directory).unwrap().is_none());
+        let expired = InventoryCache::builder()
+            .source("bitbucket")
+            .ttl(Duration::ZERO)
+            .build();
+        assert!(expired.cached(&directory).unwrap().is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn offline_without_inventory() {
+        let directory = temporary_directory("offline");
+        let cache = InventoryCache::builder().offline(true).build();
+
+        assert!(matches!(
+            cache.cached(&directory),
+            Err(errors::Error::MissingInventory(_))
+        ));
+        assert!(InventoryCache::default()
+            .cached(&directory)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn offline_with_inventory_of_other_source() {
+        let directory = temporary_directory("other");
+        InventoryCache::builder()
+            .source("github")
+            .build()
+            .save(&directory, vec![("ATLAS".to_owned(), repository())]);
+        let cache = InventoryCache::builder()
+            .source("bitbucket")
+            .offline(true)
+            .build();
+
+        assert!(matches!(
+            cache.cached(&directory),
+            Err(errors::Error::InventoryOfOtherSource { .. })
+        ));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn last_inventory_of_the_same_source() {
+        let directory = temporary_directory("last");
+        let cache = InventoryCache::builder().source("bitbucket").build();
+        cache.save(&directory, vec![("ATLAS".to_owned(), repository())]);
+
+        assert!(cache.last(&directory).is_some());
+        let other = InventoryCache::builder().source("github").build();
+        assert!(other.last(&directory).is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "gitbucket-inventory-{}-{}",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn repository() -> BitbucketRepository {
+        BitbucketRepository::builder()
+            .name("Core")
+            .ssh_url(Some(
+                "ssh://git@bitbucket.internal:7999/atlas/core.git".to_owned(),
+            ))
+            .http_url(None)
+            .default_branch(Some("trunk".to_owned()))
+            .build()
+    }
+}
@@ -0,0 +1,485 @@
+//! This module contains the verification of ssh host keys against known_hosts files
+
+use git2::cert::CertHostkey;
+use ring::{digest, hmac};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::PathBuf,
+    slice,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tracing::warn;
+
+use crate::errors;
+
+/// How to handle the host key presented by an ssh server
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyChecking {
+    /// Only connect to hosts with a matching key in known_hosts
+    #[default]
+    Strict,
+    /// Also connect to hosts missing from known_hosts, keeping their key for the run
+    AcceptNew,
+    /// Connect to any host
+    Off,
+}
+
+impl FromStr for HostKeyChecking {
+    type Err = errors::Error;
+
+    /// Parse the name of a checking, as in the configuration file
+    fn from_str(s: &str) -> errors::Result<HostKeyChecking> {
+        match s {
+            "strict" => Ok(HostKeyChecking::Strict),
+            "accept-new" => Ok(HostKeyChecking::AcceptNew),
+            "off" => Ok(HostKeyChecking::Off),
+            _ => Err(errors::Error::InvalidSetting {
+                setting: "host key checking",
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+/// The host keys read from the known_hosts files
+#[derive(Clone, Debug)]
+pub struct KnownHosts {
+    checking: HostKeyChecking,
+    entries: Vec<Entry>,
+    accepted: Arc<Mutex<HashMap<AcceptedHost, Vec<u8>>>>,
+}
+
+/// A host with the type of the key accepted for it during the run
+type AcceptedHost = (String, Option<String>);
+
+impl Default for KnownHosts {
+    /// Strict checking without known hosts, failing every ssh host
+    fn default() -> KnownHosts {
+        KnownHosts {
+            checking: HostKeyChecking::Strict,
+            entries: Vec::new(),
+            accepted: Arc::default(),
+        }
+    }
+}
+
+/// A line of a known_hosts file, with the SHA-256 and the SHA-1 hash of its key
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    marker: Option<String>,
+    hosts: String,
+    /// The key type, e.g. `ssh-ed25519`
+    key_type: String,
+    sha256: Vec<u8>,
+    sha1: Vec<u8>,
+}
+
+impl Entry {
+    /// Whether the key has the hash, SHA-256 or SHA-1 told apart by their length
+    fn has_hash(&self, hash: &[u8]) -> bool {
+        self.sha256 == hash || self.sha1 == hash
+    }
+}
+
+impl KnownHosts {
+    /// Read the known_hosts files, skipping the files that do not exist
+    pub fn new(checking: HostKeyChecking, files: &[PathBuf]) -> errors::Result<KnownHosts> {
+        let mut entries = Vec::new();
+        if checking != HostKeyChecking::Off {
+            for file in files {
+                match fs::read_to_string(file) {
+                    Ok(contents) => entries.extend(contents.lines().filter_map(parse_line)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(source) => {
+                        return Err(errors::Error::FailedToReadKnownHosts {
+                            file: file.to_string_lossy().to_string(),
+                            source,
+                        })
+                    }
+                }
+            }
+        }
+
+        Ok(KnownHosts {
+            checking,
+            entries,
+            accepted: Arc::default(),
+        })
+    }
+
+    /// Whether host keys are checked at all
+    pub(crate) fn enabled(&self) -> bool {
+        self.checking != HostKeyChecking::Off
+    }
+
+    /// Check the host key of a host, named `host` or `[host]:port` like in known_hosts, by its
+    /// SHA-256 hash or else its SHA-1 hash
+    pub(crate) fn check(&self, host: &str, hostkey: &CertHostkey<'_>) -> errors::Result<()> {
+        let hash = hostkey
+            .hash_sha256()
+            .map(|hash| hash.to_vec())
+            .or_else(|| hostkey.hash_sha1().map(|hash| hash.to_vec()))
+            .ok_or_else(|| errors::Error::MissingHostKeyHash(host.to_owned()))?;
+        self.check_hash(host, raw_key_type(hostkey).as_deref(), &hash)
+    }
+
+    /// Check the hash of a host key, only a known key of the same type is a mismatch when the
+    /// type is known, like OpenSSH
+    fn check_hash(&self, host: &str, key_type: Option<&str>, hash: &[u8]) -> errors::Result<()> {
+        let fingerprint = fingerprint(hash);
+        let entries = self
+            .entries
+            .iter()
+            .filter(|entry| entry.marker.as_deref() != Some("@cert-authority"))
+            .filter(|entry| matches_host(&entry.hosts, host));
+        let (revoked, known): (Vec<_>, Vec<_>) = entries.partition(|entry| entry.marker.is_some());
+
+        if revoked.iter().any(|entry| entry.has_hash(hash)) {
+            return Err(errors::Error::RevokedHostKey {
+                host: host.to_owned(),
+                fingerprint,
+            });
+        }
+        if known.iter().any(|entry| entry.has_hash(hash)) {
+            return Ok(());
+        }
+        // A host known by another key type only is unknown for this key type
+        let same_type = |entry: &&Entry| key_type.map_or(true, |t| entry.key_type == t);
+        if known.iter().any(same_type) {
+            return Err(errors::Error::HostKeyMismatch {
+                host: host.to_owned(),
+                fingerprint,
+            });
+        }
+
+        if self.checking == HostKeyChecking::AcceptNew {
+            let mut accepted = self.accepted.lock().unwrap();
+            let accepted_key = (host.to_owned(), key_type.map(String::from));
+            match accepted.get(&accepted_key) {
+                Some(accepted) if accepted == hash => return Ok(()),
+                Some(_) => {}
+                None => {
+                    warn!("Accepting unknown host key of {} {}", host, fingerprint);
+                    accepted.insert(accepted_key, hash.to_vec());
+                    return Ok(());
+                }
+            }
+            return Err(errors::Error::HostKeyMismatch {
+                host: host.to_owned(),
+                fingerprint,
+            });
+        }
+
+        Err(errors::Error::UnknownHostKey {
+            host: host.to_owned(),
+            fingerprint,
+        })
+    }
+}
+
+/// The key type of the raw host key libgit2 passes along with its hashes, None without one
+fn raw_key_type(hostkey: &CertHostkey<'_>) -> Option<String> {
+    // CertHostkey only holds the pointer to the libgit2 certificate, git2 casts it the same way
+    let raw = unsafe {
+        &**(hostkey as *const CertHostkey<'_> as *const *const libgit2_sys::git_cert_hostkey)
+    };
+    if raw.kind & libgit2_sys::GIT_CERT_SSH_RAW == 0 || raw.hostkey.is_null() {
+        return None;
+    }
+    let key = unsafe { slice::from_raw_parts(raw.hostkey as *const u8, raw.hostkey_len) };
+    key_type(key).map(String::from)
+}
+
+/// The key type at the start of a key in the ssh wire format, a string prefixed by its length
+fn key_type(key: &[u8]) -> Option<&str> {
+    let length = u32::from_be_bytes(key.get(..4)?.try_into().ok()?) as usize;
+    std::str::from_utf8(key.get(4..4 + length)?).ok()
+}
+
+/// The host of an ssh url as named in known_hosts, `[host]:port` for other ports than 22
+pub(crate) fn known_host(host: &str, url: &str) -> String {
+    let port = url
+        .strip_prefix("ssh://")
+        .and_then(|rest| rest.split('/').next())
+        .map(|authority| authority.rsplit('@').next().unwrap_or(authority))
+        .and_then(|authority| authority.rsplit_once(':'))
+        .map(|(_, port)| port)
+        .filter(|port| *port != "22");
+    match port {
+        Some(port) => format!("[{}]:{}", host, port),
+        None => host.to_owned(),
+    }
+}
+
+/// Whether the url uses ssh rather than http(s)
+pub(crate) fn is_ssh(url: &str) -> bool {
+    !url.starts_with("http://") && !url.starts_with("https://")
+}
+
+/// Parse a line `[marker] hosts keytype key [comment]`, None for comments and invalid lines
+fn parse_line(line: &str) -> Option<Entry> {
+    let mut fields = line.split_whitespace().peekable();
+    if fields.peek()?.starts_with('#') {
+        return None;
+    }
+    let marker = fields
+        .next_if(|field| field.starts_with('@'))
+        .map(String::from);
+    let hosts = fields.next()?.to_owned();
+    let key_type = fields.next()?.to_owned();
+    let key = base64::decode(fields.next()?).ok()?;
+
+    Some(Entry {
+        marker,
+        hosts,
+        key_type,
+        sha256: digest::digest(&digest::SHA256, &key).as_ref().to_vec(),
+        sha1: digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &key)
+            .as_ref()
+            .to_vec(),
+    })
+}
+
+/// Whether the comma-separated patterns or hashed host `|1|salt|hash` match the host
+fn matches_host(hosts: &str, host: &str) -> bool {
+    if let Some(hashed) = hosts.strip_prefix("|1|") {
+        return match hashed.split_once('|') {
+            Some((salt, hash)) => match (base64::decode(salt), base64::decode(hash)) {
+                (Ok(salt), Ok(hash)) => {
+                    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &salt);
+                    hmac::verify(&key, host.as_bytes(), &hash).is_ok()
+                }
+                _ => false,
+            },
+            None => false,
+        };
+    }
+
+    let host = host.to_lowercase();
+    let mut matched = false;
+    for pattern in hosts.split(',') {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard(&negated.to_lowercase(), &host) => return false,
+            Some(_) => {}
+            None => matched |= wildcard(&pattern.to_lowercase(), &host),
+        }
+    }
+    matched
+}
+
+/// Match a pattern with `*` and `?` wildcards
+fn wildcard(pattern: &str, text: &str) -> bool {
+    match pattern.chars().next() {
+        None => text.is_empty(),
+        Some('*') => (0..=text.len())
+            .filter(|&i| text.is_char_boundary(i))
+            .any(|i| wildcard(&pattern[1..], &text[i..])),
+        Some('?') => {
+            let mut chars = text.chars();
+            chars.next().is_some() && wildcard(&pattern[1..], chars.as_str())
+        }
+        Some(c) => text
+            .strip_prefix(c)
+            .map_or(false, |rest| wildcard(&pattern[c.len_utf8()..], rest)),
+    }
+}
+
+/// The fingerprint of a host key like OpenSSH shows it, `SHA256:` or `SHA1:` with unpadded base64
+fn fingerprint(hash: &[u8]) -> String {
+    let algorithm = match hash.len() {
+        20 => "SHA1",
+        _ => "SHA256",
+    };
+    format!(
+        "{}:{}",
+        algorithm,
+        base64::encode_config(hash, base64::STANDARD_NO_PAD)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+    const OTHER_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIBeA6DrrrJ3Ewp0+JDNIJx+lJbMAe5qLtHUG9RfTo3Gq";
+
+    fn hash(key: &str) -> Vec<u8> {
+        digest::digest(&digest::SHA256, &base64::decode(key).unwrap())
+            .as_ref()
+            .to_vec()
+    }
+
+    fn sha1(key: &str) -> Vec<u8> {
+        digest::digest(
+            &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            &base64::decode(key).unwrap(),
+        )
+        .as_ref()
+        .to_vec()
+    }
+
+    fn known_hosts(checking: HostKeyChecking, lines: &str) -> KnownHosts {
+        KnownHosts {
+            checking,
+            entries: lines.lines().filter_map(parse_line).collect(),
+            accepted: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn matching_host_key() {
+        let hosts = known_hosts(
+            HostKeyChecking::Strict,
+            &format!(
+                "# comment\nbitbucket.org,*.internal ssh-ed25519 {}\n[git.internal]:7999 ssh-ed25519 {}",
+                KEY, OTHER_KEY
+            ),
+        );
+
+        assert!(hosts.check_hash("bitbucket.org", None, &hash(KEY)).is_ok());
+        assert!(hosts.check_hash("Bitbucket.ORG", None, &hash(KEY)).is_ok());
+        assert!(hosts.check_hash("ci.internal", None, &hash(KEY)).is_ok());
+        assert!(hosts
+            .check_hash("[git.internal]:7999", None, &hash(OTHER_KEY))
+            .is_ok());
+    }
+
+    #[test]
+    fn sha1_host_key() {
+        let hosts = known_hosts(
+            HostKeyChecking::Strict,
+            &format!("bitbucket.org ssh-ed25519 {}", KEY),
+        );
+
+        assert!(hosts.check_hash("bitbucket.org", None, &sha1(KEY)).is_ok());
+        assert!(matches!(
+            hosts.check_hash("bitbucket.org", None, &sha1(OTHER_KEY)),
+            Err(errors::Error::HostKeyMismatch { fingerprint, .. }) if fingerprint.starts_with("SHA1:")
+        ));
+    }
+
+    #[test]
+    fn host_known_by_another_key_type() {
+        let hosts = known_hosts(
+            HostKeyChecking::Strict,
+            &format!("bitbucket.org ssh-ed25519 {}", KEY),
+        );
+        let ecdsa = Some("ecdsa-sha2-nistp256");
+
+        assert!(matches!(
+            hosts.check_hash("bitbucket.org", ecdsa, &hash(OTHER_KEY)),
+            Err(errors::Error::UnknownHostKey { .. })
+        ));
+        assert!(matches!(
+            hosts.check_hash("bitbucket.org", Some("ssh-ed25519"), &hash(OTHER_KEY)),
+            Err(errors::Error::HostKeyMismatch { .. })
+        ));
+        assert_eq!(key_type(&base64::decode(KEY).unwrap()), Some("ssh-ed25519"));
+    }
+
+    #[test]
+    fn strict_by_default() {
+        assert!(matches!(
+            KnownHosts::default().check_hash("bitbucket.org", None, &hash(KEY)),
+            Err(errors::Error::UnknownHostKey { .. })
+        ));
+    }
+
+    #[test]
+    fn mismatching_host_key() {
+        let hosts = known_hosts(
+            HostKeyChecking::AcceptNew,
+            &format!("bitbucket.org ssh-ed25519 {}", KEY),
+        );
+        let error = hosts
+            .check_hash("bitbucket.org", None, &hash(OTHER_KEY))
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "host key of bitbucket.org does not match known_hosts, fingerprint {}",
+                fingerprint(&hash(OTHER_KEY))
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_host_key() {
+        let strict = known_hosts(HostKeyChecking::Strict, "");
+        let accept_new = known_hosts(HostKeyChecking::AcceptNew, "");
+
+        assert!(matches!(
+            strict.check_hash("bitbucket.org", None, &hash(KEY)),
+            Err(errors::Error::UnknownHostKey { .. })
+        ));
+        assert!(accept_new
+            .check_hash("bitbucket.org", None, &hash(KEY))
+            .is_ok());
+        assert!(accept_new
+            .check_hash("bitbucket.org", None, &hash(KEY))
+            .is_ok());
+        assert!(matches!(
+            accept_new.check_hash("bitbucket.org", None, &hash(OTHER_KEY)),
+            Err(errors::Error::HostKeyMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn revoked_and_negated_hosts() {
+        let hosts = known_hosts(
+            HostKeyChecking::Strict,
+            &format!(
+                "@revoked * ssh-ed25519 {}\n*.internal,!old.internal ssh-ed25519 {}",
+                OTHER_KEY, KEY
+            ),
+        );
+
+        assert!(matches!(
+            hosts.check_hash("git.internal", None, &hash(OTHER_KEY)),
+            Err(errors::Error::RevokedHostKey { .. })
+        ));
+        assert!(matches!(
+            hosts.check_hash("old.internal", None, &hash(KEY)),
+            Err(errors::Error::UnknownHostKey { .. })
+        ));
+    }
+
+    #[test]
+    fn hashed_host() {
+        // Hashed by ssh-keygen -H
+        let hosts = "|1|UpSqXrRZd3akdnuc1c23BU2+zJ4=|LzFiDY9qKacPI3u55YTN+uQvCKM=";
+
+        assert!(matches_host(hosts, "bitbucket.org"));
+        assert!(!matches_host(hosts, "github.com"));
+    }
+
+    #[test]
+    fn fingerprint_like_openssh() {
+        assert_eq!(
+            fingerprint(&hash(KEY)),
+            "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU"
+        );
+    }
+
+    #[test]
+    fn known_host_with_port() {
+        assert_eq!(
+            known_host("bitbucket.org", "git@bitbucket.org:CORE/api.git"),
+            "bitbucket.org"
+        );
+        assert_eq!(
+            known_host("git.internal", "ssh://git@git.internal:7999/core/api.git"),
+            "[git.internal]:7999"
+        );
+        assert_eq!(
+            known_host("git.internal", "ssh://git.internal:22/core/api.git"),
+            "git.internal"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::{fs, path::Path};
+use tracing::warn;
+
+use crate::{
+    bitbucket::BitbucketCredentials,
+    errors,
+    git::{
+        clone::{local_path, repository_path, select_repository},
+        personal::PersonalRepositories,
+        pool,
+        report::{ListedRepository, Presence},
+        repository_slug, BitbucketRepository, Git,
+    },
+    provider::Provider,
+};
+
+pub async fn run(
+    git: Git,
+    provider: &dyn Provider,
+    credentials: &BitbucketCredentials,
+    personal: PersonalRepositories,
+    projects: &[String],
+    selected: bool,
+    default_branches: bool,
+) -> errors::Result<Vec<ListedRepository>> {
+    let repositories = git
+        .repositories(provider, credentials, false)
+        .filter(|repository| {
+            let project = match repository {
+                Ok((project, _)) => project,
+                Err(_) => return true,
+            };
+            projects.is_empty() || projects.iter().any(|p| p.eq_ignore_ascii_case(project))
+        });
+    let listed = git.for_each_iter(repositories, |repository| {
+        let (project, repository) = repository?;
+        pool::catch(|| {
+            Ok(list_repository(
+                &git,
+                provider,
+                credentials,
+                &personal,
+                default_branches,
+                project,
+                repository,
+            ))
+        })
+    });
+
+    let mut listed = listed
+        .into_iter()
+        .collect::<errors::Result<Vec<ListedRepository>>>()?;
+    if selected {
+        listed.retain(|repository| !matches!(repository.presence, Presence::Excluded(_)));
+    }
+    listed.sort_by(|a, b| (&a.project, &a.repository).cmp(&(&b.project, &b.repository)));
+    Ok(listed)
+}
+
+fn list_repository(
+    git: &Git,
+    provider: &dyn Provider,
+    credentials: &BitbucketCredentials,
+    personal: &PersonalRepositories,
+    default_branches: bool,
+    project: String,
+    repository: BitbucketRepository,
+) -> ListedRepository {
+    let (path, presence) = match select_repository(git, personal, &project, &repository) {
+        Ok(directory) => {
+            let path = local_path(git, &directory, &repository);
+            match path.exists() {
+                true => (path, Presence::Cloned),
+                false => (path, Presence::Missing),
+            }
+        }
+        Err(exclusion) => (
+            repository_path(git, &project, &repository),
+            Presence::Excluded(exclusion),
+        ),
+    };
+    let local_size = (presence == Presence::Cloned).then(|| directory_size(&path));
+    let clone_url = git
+        .clone_protocol
+        .urls(&repository)
+        .ok()
+        .and_then(|urls| urls.first().map(|url| url.to_string()));
+    let default_branch = match default_branches {
+        true => default_branch(git, provider, credentials, &project, &repository),
+        false => repository.default_branch.clone(),
+    };
+
+    ListedRepository {
+        project,
+        repository: repository.name,
+        path,
+        clone_url,
+        default_branch,
+        description: repository.description,
+        archived: repository.archived,
+        presence,
+        local_size,
+    }
+}
+
+/// The default branch from the listing, or else from the server unless offline
+fn default_branch(
+    git: &Git,
+    provider: &dyn Provider,
+    credentials: &BitbucketCredentials,
+    project: &str,
+    repository: &BitbucketRepository,
+) -> Option<String> {
+    if repository.default_branch.is_some() || git.inventory.offline() {
+        return repository.default_branch.clone();
+    }
+    let slug = repository_slug(repository)?;
+    git.retry
+        .run(|| provider.default_branch(credentials, project, &slug))
+        .unwrap_or_else(|e| {
+            warn!(
+                "{}/{} - Error fetching default branch - {}",
+                project, repository.name, e
+            );
+            None
+        })
+}
+
+/// The size in bytes of the files in a directory, without following symbolic links
+fn directory_size(directory: &Path) -> u64 {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => directory_size(&entry.path()),
+            Ok(file_type) if file_type.is_file() => entry.metadata().map_or(0, |m| m.len()),
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_of_nested_files() {
+        let directory = std::env::temp_dir().join(format!("gitbucket-size-{}", std::process::id()));
+        fs::create_dir_all(directory.join(".git/objects")).unwrap();
+        fs::write(directory.join("README.md"), "hello").unwrap();
+        fs::write(directory.join(".git/objects/pack"), "0123456789").unwrap();
+
+        assert_eq!(directory_size(&directory), 15);
+        assert_eq!(directory_size(&directory.join("missing")), 0);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,109 +1,645 @@
 //! This module contains the logic for implementing the Git commands
 
+pub mod branches;
 pub mod exclusions;
+pub mod inventory;
+pub mod known_hosts;
+pub mod personal;
+pub mod progress;
+pub mod report;
+pub mod ssh;
 
 mod clone;
 mod featured;
+mod list;
+mod pool;
+mod prune;
 mod pull;
 mod status;
 
-use git2::{Cred, FetchOptions, RemoteCallbacks, Repository, StatusOptions};
+use git2::{
+    Config, Cred, CredentialType, ErrorClass, ErrorCode, FetchOptions, RemoteCallbacks, Repository,
+    StatusOptions,
+};
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
 use std::{
-    fs::{self, DirEntry},
+    cell::RefCell,
+    collections::HashMap,
+    ffi::OsStr,
+    fs, iter,
     path::{Path, PathBuf},
+    str::FromStr,
 };
+use tracing::trace;
 use typed_builder::TypedBuilder;
 
 use crate::{
     bitbucket::{BitbucketCredentials, BitbucketRepository},
     errors,
-    git::exclusions::Exclusions,
+    git::{
+        branches::MainBranches,
+        exclusions::Exclusions,
+        inventory::InventoryCache,
+        known_hosts::KnownHosts,
+        personal::PersonalRepositories,
+        progress::Progress,
+        report::{ListedRepository, Report},
+        ssh::SshKeys,
+    },
+    network::Network,
+    provider::Provider,
+    retry::RetryPolicy,
 };
 
+/// The default number of repositories handled at the same time
+pub const DEFAULT_JOBS: usize = 8;
+/// The directory in the root directory with the archived repositories, by project
+pub const ARCHIVED_DIRECTORY: &str = "_archived";
+
 #[derive(TypedBuilder, Clone, Debug)]
 pub struct Git {
     #[builder(setter(into))]
     root_directory: String,
-    #[builder(setter(into))]
-    private_key_location: PathBuf,
+    #[builder(default)]
+    ssh_keys: SshKeys,
+    #[builder(default)]
+    known_hosts: KnownHosts,
+    #[builder(default)]
+    network: Network,
     dry_run: bool,
+    #[builder(default)]
+    clone_protocol: CloneProtocol,
+    #[builder(default)]
+    archived: ArchivedRepositories,
+    #[builder(default)]
+    exclusions: Exclusions,
+    #[builder(default)]
+    main_branches: MainBranches,
+    #[builder(default = DEFAULT_JOBS)]
+    jobs: usize,
+    #[builder(default)]
+    retry: RetryPolicy,
+    #[builder(default)]
+    progress: Progress,
+    #[builder(default)]
+    inventory: InventoryCache,
+    /// The number of directories a project spans locally, more for GitLab subgroups
+    #[builder(default = 1)]
+    project_depth: usize,
+}
+
+/// The protocol used to clone new repositories
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CloneProtocol {
+    /// Clone using the ssh link
+    #[default]
+    Ssh,
+    /// Clone using the http(s) link
+    Https,
+    /// Clone using the ssh link, falling back to the http(s) link
+    Auto,
+}
+
+/// What is done with the repositories that are archived on the server
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchivedRepositories {
+    /// Leave them out of clones
+    #[default]
+    Skip,
+    /// Clone them into the archived directory, `_archived/PROJECT/repository`
+    Separate,
+}
+
+impl FromStr for CloneProtocol {
+    type Err = errors::Error;
+
+    /// Parse the name of a protocol, as in the configuration file
+    fn from_str(s: &str) -> errors::Result<CloneProtocol> {
+        match s {
+            "ssh" => Ok(CloneProtocol::Ssh),
+            "https" => Ok(CloneProtocol::Https),
+            "auto" => Ok(CloneProtocol::Auto),
+            _ => Err(errors::Error::InvalidSetting {
+                setting: "clone protocol",
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+impl FromStr for ArchivedRepositories {
+    type Err = errors::Error;
+
+    /// Parse the name of a choice, as in the configuration file
+    fn from_str(s: &str) -> errors::Result<ArchivedRepositories> {
+        match s {
+            "skip" => Ok(ArchivedRepositories::Skip),
+            "separate" => Ok(ArchivedRepositories::Separate),
+            _ => Err(errors::Error::InvalidSetting {
+                setting: "archived repositories",
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+impl CloneProtocol {
+    /// The clone urls of a repository to try, in order
+    fn urls<'a>(&self, repository: &'a BitbucketRepository) -> errors::Result<Vec<&'a str>> {
+        let ssh_url = repository.ssh_url.as_deref();
+        let http_url = repository.http_url.as_deref();
+        let urls = match self {
+            CloneProtocol::Ssh => vec![ssh_url.ok_or(errors::Error::SshUrlMissing)?],
+            CloneProtocol::Https => vec![http_url.ok_or(errors::Error::HttpUrlMissing)?],
+            CloneProtocol::Auto => [ssh_url, http_url].into_iter().flatten().collect(),
+        };
+        match urls.is_empty() {
+            true => Err(errors::Error::SshUrlMissing),
+            false => Ok(urls),
+        }
+    }
 }
 
 impl Git {
+    /// Clone the selected repositories of the provider that do not exist locally
     pub async fn clone_command(
         self,
-        bitbucket_root_url: &str,
+        provider: &dyn Provider,
+        credentials: &BitbucketCredentials,
+        personal: PersonalRepositories,
+    ) -> errors::Result<Report> {
+        clone::run(self, provider, credentials, personal).await
+    }
+
+    /// Report whether the repositories are on a main or a feature branch, with the default
+    /// branch of every repository taken from the provider when given, finding the personal
+    /// repositories in their personal directory
+    pub async fn featured_command(
+        self,
+        remote: Option<(&dyn Provider, &BitbucketCredentials, &PersonalRepositories)>,
+    ) -> errors::Result<Report> {
+        featured::run(self, remote).await
+    }
+
+    /// List the repositories of the provider in the projects, all when none are given, with
+    /// whether they are cloned locally. `selected` leaves out the excluded repositories,
+    /// `default_branches` fetches the default branches missing from the listing.
+    pub async fn list_command(
+        self,
+        provider: &dyn Provider,
         credentials: &BitbucketCredentials,
-        excluded_projects: Exclusions,
-    ) -> errors::Result<()> {
-        clone::run(self, bitbucket_root_url, credentials, excluded_projects).await
+        personal: PersonalRepositories,
+        projects: &[String],
+        selected: bool,
+        default_branches: bool,
+    ) -> errors::Result<Vec<ListedRepository>> {
+        list::run(
+            self,
+            provider,
+            credentials,
+            personal,
+            projects,
+            selected,
+            default_branches,
+        )
+        .await
     }
 
-    pub async fn featured_command(self, show_main: bool) -> errors::Result<()> {
-        featured::run(self, show_main).await
+    /// Report, relocate or delete the local repositories that are no longer on the server
+    pub async fn prune_command(
+        self,
+        provider: &dyn Provider,
+        credentials: &BitbucketCredentials,
+        personal: PersonalRepositories,
+        relocate: bool,
+        delete: bool,
+    ) -> errors::Result<Report> {
+        prune::run(self, provider, credentials, personal, relocate, delete).await
     }
 
-    pub async fn pull_command(self, show_errors: bool) -> errors::Result<()> {
-        pull::run(self, show_errors).await
+    /// Fast-forward the clean repositories to their remote branch, fetching over http(s) with
+    /// the given credentials
+    pub async fn pull_command(
+        self,
+        credentials: Option<&BitbucketCredentials>,
+    ) -> errors::Result<Report> {
+        pull::run(self, credentials).await
     }
 
-    pub async fn status_command(self) -> errors::Result<()> {
+    /// Report whether the repositories have changes in their workspace
+    pub async fn status_command(self) -> errors::Result<Report> {
         status::run(self).await
     }
 
-    fn fetch_options(&self) -> FetchOptions {
+    /// Clone or fetch from the url with the fetch options, reporting a rejected host key
+    /// instead of the error libgit2 makes of it
+    fn fetch<T>(
+        &self,
+        url: &str,
+        credentials: Option<&BitbucketCredentials>,
+        bar: &ProgressBar,
+        operation: impl FnOnce(FetchOptions<'_>) -> Result<T, git2::Error>,
+    ) -> errors::Result<T> {
+        let rejection = RefCell::new(None);
+        let result = operation(self.fetch_options(url, credentials, bar, &rejection));
+        result.map_err(|e| rejection.into_inner().unwrap_or(e.into()))
+    }
+
+    /// The fetch options authenticating with the ssh key, or over http(s) with the given
+    /// credentials, falling back to the git credential helper, showing the transfer progress
+    fn fetch_options<'a>(
+        &'a self,
+        url: &'a str,
+        credentials: Option<&'a BitbucketCredentials>,
+        bar: &'a ProgressBar,
+        rejection: &'a RefCell<Option<errors::Error>>,
+    ) -> FetchOptions<'a> {
         // Prepare callbacks.
+        let mut userpass_tried = false;
+        let mut ssh_attempts = self.ssh_keys.attempts(&self.progress);
         let mut callbacks = RemoteCallbacks::new();
-        callbacks.credentials(move |_url, username_from_url, _allowed_types| {
-            Cred::ssh_key(
-                username_from_url.unwrap(),
-                None,
-                &self.private_key_location,
-                None,
-            )
+        callbacks.credentials(move |url, username_from_url, allowed_types| {
+            // Urls without a user, e.g. ssh://host/repo.git, authenticate as git
+            let username = username_from_url.unwrap_or("git");
+            if allowed_types.contains(CredentialType::USERNAME) {
+                return Cred::username(username);
+            }
+            if allowed_types.contains(CredentialType::SSH_KEY) {
+                return ssh_attempts.next(username);
+            }
+            if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                // libgit2 keeps asking while the server rejects the credentials
+                if userpass_tried {
+                    return Err(git2::Error::new(
+                        ErrorCode::Auth,
+                        ErrorClass::Http,
+                        "http authentication failed",
+                    ));
+                }
+                userpass_tried = true;
+                return match credentials {
+                    Some(credentials) => {
+                        let (username, password) = credentials.userpass();
+                        Cred::userpass_plaintext(username, password)
+                    }
+                    None => {
+                        Cred::credential_helper(&Config::open_default()?, url, username_from_url)
+                    }
+                };
+            }
+            Cred::default()
+        });
+        // Only for ssh, accepting a certificate would skip the verification of https
+        if self.known_hosts.enabled() && known_hosts::is_ssh(url) {
+            callbacks.certificate_check(move |cert, host| match cert.as_hostkey() {
+                Some(hostkey) => {
+                    let host = known_hosts::known_host(host, url);
+                    match self.known_hosts.check(&host, hostkey) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            *rejection.borrow_mut() = Some(e);
+                            false
+                        }
+                    }
+                }
+                None => true,
+            });
+        }
+        callbacks.transfer_progress(|stats| {
+            progress::transfer(bar, stats);
+            true
+        });
+        callbacks.sideband_progress(|data| {
+            progress::sideband(bar, data);
+            true
         });
 
         // Prepare fetch options.
         let mut fo = FetchOptions::new();
         fo.remote_callbacks(callbacks);
+        fo.proxy_options(self.network.git_proxy(url));
         fo
     }
 
+    /// Run the task for every item on the bounded worker pool
+    fn for_each<T, R, F>(&self, items: &[T], task: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync,
+    {
+        pool::run(self.jobs, items, task)
+    }
+
+    /// Run the task for every item of the iterator on the bounded worker pool
+    fn for_each_iter<I, R, F>(&self, items: I, task: F) -> Vec<R>
+    where
+        I: Iterator + Send,
+        R: Send,
+        F: Fn(I::Item) -> R + Sync,
+    {
+        pool::run_iter(self.jobs, items, task)
+    }
+
+    /// The repositories of the provider with their project, from the inventory while it is
+    /// fresh unless a fresh listing is asked for, and always offline. Otherwise a page is fetched
+    /// when it is needed and a failed page is retried. The listing ends after a page that keeps
+    /// failing, a complete listing becomes the inventory.
+    fn repositories<'a>(
+        &'a self,
+        provider: &'a dyn Provider,
+        credentials: &'a BitbucketCredentials,
+        fresh: bool,
+    ) -> Box<dyn Iterator<Item = errors::Result<(String, BitbucketRepository)>> + Send + 'a> {
+        if !fresh || self.inventory.offline() {
+            match self.inventory.cached(Path::new(&self.root_directory)) {
+                Ok(Some(inventory)) => return Box::new(inventory.repositories().map(Ok)),
+                Ok(None) => {}
+                Err(e) => return Box::new(iter::once(Err(e))),
+            }
+        }
+
+        let mut pages = provider.repository_pages(credentials);
+        // The repositories listed so far, None after a failed page
+        let mut listed = Some(Vec::new());
+        let pages = iter::from_fn(move || {
+            listed.as_ref()?;
+            match self.retry.run(|| pages.next().transpose()).transpose() {
+                Some(Ok(page)) => {
+                    listed.as_mut()?.extend(page.iter().cloned());
+                    Some(Ok(page))
+                }
+                Some(Err(e)) => {
+                    listed = None;
+                    Some(Err(e))
+                }
+                None => {
+                    let listed = listed.take()?;
+                    if !self.dry_run {
+                        self.inventory.save(Path::new(&self.root_directory), listed);
+                    }
+                    None
+                }
+            }
+        });
+        Box::new(pages.flat_map(|page| match page {
+            Ok(page) => page.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        }))
+    }
+
+    /// The default branches in the inventory by project and repository slug, when it is fresh
+    fn cached_default_branches(&self) -> errors::Result<HashMap<(String, String), String>> {
+        let inventory = self.inventory.cached(Path::new(&self.root_directory))?;
+        Ok(inventory
+            .map(|inventory| inventory.default_branches())
+            .unwrap_or_default())
+    }
+
+    /// The git repositories in the project directories of the root directory, a level deeper in
+    /// the archived directory
     fn get_existing_git_projects(&self) -> errors::Result<Vec<PathBuf>> {
-        let paths = fs::read_dir(&self.root_directory)
-            .map_err(|source| errors::Error::FailedToReadDirectory {
-                directory: (&self.root_directory).clone(),
-                source,
-            })?
-            .filter(|dir_entry| self.is_dir_and_not_symlink(dir_entry.as_ref().unwrap()))
-            .map(|dir_entry| dir_entry.unwrap().path())
-            .flat_map(|path| self.get_existing_git_repos(&path).unwrap())
-            .collect::<Vec<PathBuf>>();
+        let mut paths = Vec::new();
+        for directory in subdirectories(Path::new(&self.root_directory))? {
+            let depth = match directory.file_name() == Some(OsStr::new(ARCHIVED_DIRECTORY)) {
+                true => self.project_depth + 1,
+                false => self.project_depth,
+            };
+            paths.extend(get_existing_git_repos(&directory, depth)?);
+        }
+
+        Ok(paths
+            .into_iter()
+            .filter(|path| !self.is_excluded(path))
+            .collect())
+    }
+
+    /// The project and repository name of a local repository, based on its path relative to the
+    /// root directory
+    fn project_and_name(&self, path: &Path) -> (String, String) {
+        let relative = path.strip_prefix(&self.root_directory).unwrap_or(path);
+        let project = relative
+            .parent()
+            .map(|parent| parent.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+        let repository = relative
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
 
-        Ok(paths)
+        (project, repository)
+    }
+
+    /// The project directory of a selected repository on the server, in the archived directory
+    /// when it is archived and separated
+    fn project_directory(&self, directory: String, repository: &BitbucketRepository) -> String {
+        match repository.archived && self.archived == ArchivedRepositories::Separate {
+            true => format!("{}/{}", ARCHIVED_DIRECTORY, directory),
+            false => directory,
+        }
+    }
+
+    /// Whether a local repository is excluded, by the project it has on the server
+    fn is_excluded(&self, path: &Path) -> bool {
+        let (project, repository) = self.project_and_name(path);
+        let project = server_project(&project).0;
+        let excluded = self.exclusions.is_excluded(project, &repository);
+        if excluded {
+            trace!("Skipping {} - excluded", path.to_string_lossy());
+        }
+        excluded
     }
 
     fn is_clean(repository: &Repository) -> errors::Result<bool> {
         let statuses = repository.statuses(Some(StatusOptions::new()).as_mut())?;
         Ok(statuses.is_empty())
     }
+}
+
+/// Get the git repositories in a project directory spanning `depth` directories, descending
+/// into nested project directories such as GitLab subgroups
+fn get_existing_git_repos(dir: &Path, depth: usize) -> errors::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for path in subdirectories(dir)? {
+        if path.join(".git").exists() {
+            paths.push(path);
+        } else if depth > 1 {
+            paths.extend(get_existing_git_repos(&path, depth - 1)?);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// The directories in a directory, without symbolic links
+fn subdirectories(dir: &Path) -> errors::Result<Vec<PathBuf>> {
+    let read_error = |source| errors::Error::FailedToReadDirectory {
+        directory: dir.to_string_lossy().into_owned(),
+        source,
+    };
+    let mut directories = Vec::new();
+    for dir_entry in fs::read_dir(dir).map_err(read_error)? {
+        let dir_entry = dir_entry.map_err(read_error)?;
+        let file_type = dir_entry.file_type().map_err(read_error)?;
+        if file_type.is_dir() && !file_type.is_symlink() {
+            directories.push(dir_entry.path());
+        }
+    }
+
+    Ok(directories)
+}
+
+/// The project on the server of a local project directory, and whether the directory is in the
+/// archived directory
+fn server_project(project: &str) -> (&str, bool) {
+    match project
+        .strip_prefix(ARCHIVED_DIRECTORY)
+        .and_then(|project| project.strip_prefix('/'))
+    {
+        Some(project) => (project, true),
+        None => (project, false),
+    }
+}
+
+/// The project on the server of a local project directory, without the archived directory and
+/// with a personal directory as the `~user` project of its owner
+fn server_project_key(project: &str, personal: &PersonalRepositories) -> String {
+    let (project, _) = server_project(project);
+    match personal.owner(project) {
+        Some(owner) => format!("~{}", owner),
+        None => project.to_owned(),
+    }
+}
+
+/// The branch the head of a repository is on, if any
+fn head_branch(repo: &Repository) -> Option<String> {
+    let head = repo.head().ok()?;
+    match head.is_branch() {
+        true => head.shorthand().map(str::to_owned),
+        false => None,
+    }
+}
+
+/// The slug of a local repository, taken from its origin url or else its directory name
+fn local_slug(repo: &Repository, directory: &Path) -> String {
+    repo.find_remote("origin")
+        .ok()
+        .and_then(|remote| remote.url().and_then(url_slug))
+        .or_else(|| {
+            directory
+                .file_name()
+                .map(|name| name.to_string_lossy().to_lowercase())
+        })
+        .unwrap_or_default()
+}
+
+/// The slug of a repository on the server, from the listing or else from its clone url
+fn repository_slug(repository: &BitbucketRepository) -> Option<String> {
+    match &repository.slug {
+        Some(slug) => Some(slug.to_lowercase()),
+        None => repository
+            .ssh_url
+            .as_deref()
+            .or(repository.http_url.as_deref())
+            .and_then(url_slug),
+    }
+}
+
+/// The repository slug of a clone url, e.g. "repo" for "ssh://git@host:7999/project/repo.git"
+fn url_slug(url: &str) -> Option<String> {
+    let slug = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()?
+        .trim_end_matches(".git");
+    match slug.is_empty() {
+        true => None,
+        false => Some(slug.to_lowercase()),
+    }
+}
 
-    fn get_existing_git_repos(&self, dir: &Path) -> errors::Result<Vec<PathBuf>> {
-        let paths = fs::read_dir(dir)
-            .map_err(|source| errors::Error::FailedToReadDirectory {
-                directory: (&self.root_directory).clone(),
-                source,
-            })?
-            .filter(|dir_entry| self.is_dir_and_not_symlink(dir_entry.as_ref().unwrap()))
-            .map(|dir_entry| dir_entry.unwrap().path())
-            .filter(|path| path.join(".git").exists())
-            .collect::<Vec<PathBuf>>();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(paths)
+    #[test]
+    fn slug_from_url() {
+        assert_eq!(
+            url_slug("ssh://git@bitbucket.example.com:7999/atlas/core.git"),
+            Some("core".to_owned())
+        );
+        assert_eq!(
+            url_slug("https://user@bitbucket.example.com/scm/atlas/Core.git"),
+            Some("core".to_owned())
+        );
+        assert_eq!(
+            url_slug("git@github.com:octocat/hello"),
+            Some("hello".to_owned())
+        );
+        assert_eq!(url_slug(""), None);
     }
 
-    fn is_dir_and_not_symlink(&self, dir_entry: &DirEntry) -> bool {
-        let file_type = dir_entry.file_type().unwrap();
-        file_type.is_dir() && !file_type.is_symlink()
+    #[test]
+    fn archived_project_directory() {
+        let archived = BitbucketRepository::builder()
+            .name("old")
+            .archived(true)
+            .build();
+        let separate = Git::builder()
+            .root_directory("/src")
+            .dry_run(false)
+            .archived(ArchivedRepositories::Separate)
+            .build();
+
+        assert_eq!(
+            separate.project_directory("CORE".to_owned(), &archived),
+            "_archived/CORE"
+        );
+        assert_eq!(server_project("_archived/CORE"), ("CORE", true));
+        assert_eq!(server_project("CORE"), ("CORE", false));
+        assert_eq!(server_project("_archivedCORE"), ("_archivedCORE", false));
+    }
+
+    #[test]
+    fn existing_repositories_to_the_project_depth() {
+        let root = std::env::temp_dir().join(format!("gitbucket-depth-{}", std::process::id()));
+        for repository in [
+            "CORE/api",
+            "_archived/CORE/old",
+            "group/sub/web",
+            "scratch/x/y/z",
+        ] {
+            fs::create_dir_all(root.join(repository).join(".git")).unwrap();
+        }
+        let git = |depth: usize| {
+            Git::builder()
+                .root_directory(root.to_string_lossy())
+                .dry_run(false)
+                .project_depth(depth)
+                .build()
+        };
+        let existing = |git: Git| {
+            let mut paths = git.get_existing_git_projects().unwrap();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(
+            existing(git(1)),
+            vec![root.join("CORE/api"), root.join("_archived/CORE/old")]
+        );
+        assert_eq!(
+            existing(git(2)),
+            vec![
+                root.join("CORE/api"),
+                root.join("_archived/CORE/old"),
+                root.join("group/sub/web")
+            ]
+        );
+
+        fs::remove_dir_all(root).unwrap();
     }
 }
@@ -0,0 +1,103 @@
+use crate::bitbucket::BitbucketRepository;
+
+/// The placeholder for the owner in the personal directory pattern
+const USER_PLACEHOLDER: &str = "{user}";
+
+/// Selects the personal repositories to clone and the directory they are placed in
+#[derive(Debug)]
+pub struct PersonalRepositories {
+    users: Option<Vec<String>>,
+    directory: String,
+}
+
+impl PersonalRepositories {
+    /// Include the personal repositories of the given users, or of the current user when the
+    /// list is empty. `None` excludes all personal repositories.
+    pub fn new(users: Option<Vec<String>>, current_user: &str, directory: &str) -> Self {
+        let users = users.map(|users| match users.is_empty() {
+            true => vec![current_user.to_lowercase()],
+            false => users
+                .iter()
+                .map(|user| user.trim().to_lowercase())
+                .collect(),
+        });
+
+        PersonalRepositories {
+            users,
+            directory: directory.to_owned(),
+        }
+    }
+
+    /// The project directory for a personal repository, or None when its owner is not selected
+    pub fn project_directory(&self, repository: &BitbucketRepository) -> Option<String> {
+        let owner = repository.owner.as_ref()?;
+        self.users
+            .as_ref()?
+            .contains(&owner.to_lowercase())
+            .then(|| self.directory.replace(USER_PLACEHOLDER, owner))
+    }
+
+    /// The owner of a personal directory, the reverse of `project_directory`, None for other
+    /// directories
+    pub fn owner(&self, project_directory: &str) -> Option<String> {
+        let (prefix, suffix) = self.directory.split_once(USER_PLACEHOLDER)?;
+        let owner = project_directory
+            .strip_prefix(prefix)?
+            .strip_suffix(suffix)?;
+        (!owner.is_empty() && !owner.contains('/')).then(|| owner.to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_user() {
+        let personal = PersonalRepositories::new(Some(vec![]), "Alice", "~{user}");
+
+        assert_eq!(
+            personal.project_directory(&repository("alice")),
+            Some("~alice".to_owned())
+        );
+        assert_eq!(personal.project_directory(&repository("bob")), None);
+    }
+
+    #[test]
+    fn named_users() {
+        let personal = PersonalRepositories::new(
+            Some(vec!["bob".to_owned(), " carol".to_owned()]),
+            "alice",
+            "personal/{user}",
+        );
+
+        assert_eq!(personal.project_directory(&repository("alice")), None);
+        assert_eq!(
+            personal.project_directory(&repository("carol")),
+            Some("personal/carol".to_owned())
+        );
+    }
+
+    #[test]
+    fn owner_of_a_personal_directory() {
+        let personal = PersonalRepositories::new(None, "alice", "personal/{user}");
+
+        assert_eq!(personal.owner("personal/Bob"), Some("bob".to_owned()));
+        assert_eq!(personal.owner("personal"), None);
+        assert_eq!(personal.owner("CORE"), None);
+    }
+
+    #[test]
+    fn not_included() {
+        let personal = PersonalRepositories::new(None, "alice", "~{user}");
+
+        assert_eq!(personal.project_directory(&repository("alice")), None);
+    }
+
+    fn repository(owner: &str) -> BitbucketRepository {
+        BitbucketRepository::builder()
+            .name("name")
+            .owner(Some(owner.to_owned()))
+            .build()
+    }
+}
@@ -0,0 +1,145 @@
+//! This module contains the bounded worker pool shared by the commands
+
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::errors;
+
+/// Run the task for every item on at most `jobs` threads, so the server is not flooded with
+/// connections. The results are in the order of the items.
+pub(crate) fn run<T, R, F>(jobs: usize, items: &[T], task: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let workers = jobs.clamp(1, items.len().max(1));
+    collect(workers, || {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        items.get(index).map(|item| (index, task(item)))
+    })
+}
+
+/// Run the task for every item of an iterator on at most `jobs` threads, taking the next item
+/// when a thread is done, e.g. while later pages of a listing are still to be fetched. The
+/// results are in the order of the items.
+pub(crate) fn run_iter<I, R, F>(jobs: usize, items: I, task: F) -> Vec<R>
+where
+    I: Iterator + Send,
+    R: Send,
+    F: Fn(I::Item) -> R + Sync,
+{
+    let items = Mutex::new(items.enumerate());
+    collect(jobs.max(1), || {
+        let (index, item) = items.lock().unwrap().next()?;
+        Some((index, task(item)))
+    })
+}
+
+/// Run a task, a panic becomes an error so it only fails the item it happened for
+pub(crate) fn catch<R>(task: impl FnOnce() -> errors::Result<R>) -> errors::Result<R> {
+    panic::catch_unwind(AssertUnwindSafe(task))
+        .unwrap_or_else(|panic| Err(errors::Error::Panicked(panic_message(panic.as_ref()))))
+}
+
+/// The message a panic was started with
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => (*message).to_owned(),
+        None => panic
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| String::from("unknown panic")),
+    }
+}
+
+/// Run the workers until they are out of items, sorting their results by the index of the item
+fn collect<R, W>(workers: usize, work: W) -> Vec<R>
+where
+    R: Send,
+    W: Fn() -> Option<(usize, R)> + Sync,
+{
+    let mut results = thread::scope(|s| {
+        let handles = (0..workers)
+            .map(|_| s.spawn(|| std::iter::from_fn(&work).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<Vec<(usize, R)>>()
+    });
+
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn results_in_order() {
+        let items = (0..100).collect::<Vec<u32>>();
+        let results = run(4, &items, |item| item * 2);
+
+        assert_eq!(results, (0..100).map(|item| item * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn bounded_concurrency() {
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let items = (0..20).collect::<Vec<u32>>();
+        run(3, &items, |_| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            running.fetch_sub(1, Ordering::SeqCst);
+        });
+
+        assert!(max_running.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn iterator_results_in_order() {
+        let results = run_iter(4, (0..100u32).map(|item| item * 2), |item| item + 1);
+
+        assert_eq!(
+            results,
+            (0..100).map(|item| item * 2 + 1).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn panic_fails_its_item() {
+        let items = (0..10).collect::<Vec<u32>>();
+        let results = run(4, &items, |item| {
+            catch(|| match item {
+                3 => panic!("item {}", item),
+                _ => Ok(*item),
+            })
+        });
+
+        assert_eq!(results.len(), 10);
+        assert!(
+            matches!(&results[3], Err(errors::Error::Panicked(message)) if message == "item 3")
+        );
+        assert_eq!(results[4].as_ref().ok(), Some(&4));
+    }
+
+    #[test]
+    fn no_items() {
+        let results = run(4, &[] as &[u32], |item| *item);
+
+        assert!(results.is_empty());
+    }
+}
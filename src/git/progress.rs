@@ -0,0 +1,134 @@
+//! This module contains the live progress display of clones and fetches
+
+use git2::Progress as TransferProgress;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use std::io::{self, Write};
+
+/// The live progress of the repositories being cloned or fetched, hidden when disabled
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    bars: Option<MultiProgress>,
+}
+
+impl Progress {
+    /// Show the progress on stderr when enabled, e.g. when it is a terminal and there is no JSON
+    /// output
+    pub fn new(enabled: bool) -> Progress {
+        Progress {
+            bars: enabled.then(MultiProgress::new),
+        }
+    }
+
+    /// A writer of log lines that hides the progress while writing, so the bars are drawn below
+    /// the lines instead of through them
+    pub fn writer<W: Write>(&self, inner: W) -> ProgressWriter<W> {
+        ProgressWriter {
+            progress: self.clone(),
+            inner,
+        }
+    }
+
+    /// Hide the progress while running the function, e.g. to prompt for a passphrase
+    pub(crate) fn suspend<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        match &self.bars {
+            Some(bars) => bars.suspend(f),
+            None => f(),
+        }
+    }
+
+    /// The progress of all repositories of a command, shown below the repositories
+    pub(crate) fn overall(&self, total: usize) -> ProgressBar {
+        match &self.bars {
+            Some(bars) => bars.add(ProgressBar::new(total as u64).with_style(style(
+                "[{elapsed_precise}] {wide_bar} {pos}/{len} repositories",
+            ))),
+            None => ProgressBar::hidden(),
+        }
+    }
+
+    /// The progress of a single repository, shown above the overall progress
+    pub(crate) fn repository(&self, name: &str) -> ProgressBar {
+        match &self.bars {
+            Some(bars) => bars.insert_from_back(
+                1,
+                ProgressBar::new(0)
+                    .with_style(style("{prefix:30!} {bar:30} {pos}/{len} objects {msg}"))
+                    .with_prefix(name.to_owned()),
+            ),
+            None => ProgressBar::hidden(),
+        }
+    }
+}
+
+/// Writes log lines while the progress is hidden
+pub struct ProgressWriter<W> {
+    progress: Progress,
+    inner: W,
+}
+
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        self.progress.suspend(|| inner.write(buf))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let inner = &mut self.inner;
+        self.progress.suspend(|| inner.write_all(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Show the received objects and bytes of a transfer
+pub(crate) fn transfer(bar: &ProgressBar, progress: TransferProgress<'_>) {
+    bar.set_length(progress.total_objects() as u64);
+    bar.set_position(progress.received_objects() as u64);
+    bar.set_message(HumanBytes(progress.received_bytes() as u64).to_string());
+}
+
+/// Show the last line of the remote's messages, e.g. "Counting objects: 45% (9/20)"
+pub(crate) fn sideband(bar: &ProgressBar, data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+    if let Some(line) = text.rsplit(['\r', '\n']).find(|l| !l.trim().is_empty()) {
+        bar.set_message(line.trim().to_owned());
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template).expect("valid progress template")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sideband_shows_last_line() {
+        let bar = ProgressBar::hidden();
+        sideband(
+            &bar,
+            b"Enumerating objects: 20, done.\nCounting objects:  45% (9/20)\r",
+        );
+
+        assert_eq!(bar.message(), "Counting objects:  45% (9/20)");
+    }
+
+    #[test]
+    fn disabled_progress_is_hidden() {
+        let progress = Progress::new(false);
+
+        assert!(progress.overall(10).is_hidden());
+        assert!(progress.repository("CORE/api").is_hidden());
+    }
+
+    #[test]
+    fn writer_passes_lines_through() {
+        let mut writer = Progress::new(true).writer(Vec::new());
+        writeln!(writer, "CORE/api - fast-forwarding").unwrap();
+
+        assert_eq!(writer.inner, b"CORE/api - fast-forwarding\n");
+    }
+}
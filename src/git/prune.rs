@@ -0,0 +1,354 @@
+use git2::{ErrorCode, Repository, StatusOptions};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tracing::trace;
+
+use crate::{
+    bitbucket::BitbucketCredentials,
+    errors,
+    git::{
+        clone::repository_path,
+        head_branch, local_slug,
+        personal::PersonalRepositories,
+        report::{Outcome, PendingReport, Report, Untouched},
+        repository_slug, BitbucketRepository, Git,
+    },
+    network,
+    provider::Provider,
+};
+
+pub async fn run(
+    git: Git,
+    provider: &dyn Provider,
+    credentials: &BitbucketCredentials,
+    personal: PersonalRepositories,
+    relocate: bool,
+    delete: bool,
+) -> errors::Result<Report> {
+    // A deleted repository must not be told from an outdated inventory
+    if delete && git.inventory.offline() {
+        return Err(errors::Error::OfflineDelete);
+    }
+    let start = Instant::now();
+    // Repositories deleted or moved since the inventory was listed are only on a fresh listing
+    let server_repositories = git
+        .repositories(provider, credentials, true)
+        .collect::<errors::Result<Vec<_>>>()?;
+    let hosts = server_hosts(&server_repositories);
+    let remote = remote_locations(&git, server_repositories, &personal);
+
+    // One at a time, relocated repositories must not race for the same target
+    let mut repositories = Vec::new();
+    for directory in git.get_existing_git_projects()? {
+        if remote.contains_key(&directory) || !on_server_host(&directory, &hosts) {
+            continue;
+        }
+        let span = tracing::info_span!("prune", "{}", directory.to_string_lossy());
+        let report = PendingReport::new(&git, &directory).run(|report| {
+            span.in_scope(|| {
+                prune(
+                    &git,
+                    credentials,
+                    &remote,
+                    &directory,
+                    relocate,
+                    delete,
+                    report,
+                )
+            })
+        });
+        repositories.push(report);
+    }
+
+    Ok(Report::new(&git, repositories, start))
+}
+
+/// The hosts in the clone urls of the repositories on the server
+fn server_hosts(repositories: &[(String, BitbucketRepository)]) -> HashSet<String> {
+    repositories
+        .iter()
+        .flat_map(|(_, repository)| [&repository.ssh_url, &repository.http_url])
+        .flatten()
+        .map(|url| network::host(url))
+        .collect()
+}
+
+/// Whether a local repository was cloned from one of the hosts, other clones in the root
+/// directory are left alone. A repository that cannot be opened is reported.
+fn on_server_host(directory: &Path, hosts: &HashSet<String>) -> bool {
+    let repo = match Repository::open(directory) {
+        Ok(repo) => repo,
+        Err(_) => return true,
+    };
+    let origin = repo.find_remote("origin");
+    let url = origin.as_ref().ok().and_then(|origin| origin.url());
+    match url {
+        Some(url) => hosts.contains(&network::host(url)),
+        None => {
+            trace!("Skipping {} - no origin", directory.to_string_lossy());
+            false
+        }
+    }
+}
+
+/// The local location of every repository on the server
+fn remote_locations(
+    git: &Git,
+    repositories: Vec<(String, BitbucketRepository)>,
+    personal: &PersonalRepositories,
+) -> HashMap<PathBuf, BitbucketRepository> {
+    repositories
+        .into_iter()
+        .map(|(project, repository)| {
+            let directory = personal.project_directory(&repository).unwrap_or(project);
+            let directory = git.project_directory(directory, &repository);
+            (repository_path(git, &directory, &repository), repository)
+        })
+        .collect()
+}
+
+fn prune(
+    git: &Git,
+    credentials: &BitbucketCredentials,
+    remote: &HashMap<PathBuf, BitbucketRepository>,
+    directory: &Path,
+    relocate: bool,
+    delete: bool,
+    report: &mut PendingReport,
+) -> errors::Result<Outcome> {
+    trace!("Checking repository");
+    let repo = Repository::open(directory)?;
+    report.branch = head_branch(&repo);
+    report.clean = Some(Git::is_clean(&repo)?);
+    let slug = local_slug(&repo, directory);
+    let candidates = remote
+        .iter()
+        .filter(|(_, repository)| repository_slug(repository).as_ref() == Some(&slug))
+        .collect::<Vec<(&PathBuf, &BitbucketRepository)>>();
+
+    match candidates.as_slice() {
+        [] => not_on_server(git, repo, directory, delete),
+        [(target, repository)] => {
+            // Another repository with the same slug is not where this one moved to
+            match shares_history(git, credentials, &repo, repository)? {
+                Some(true) => {}
+                Some(false) => return not_on_server(git, repo, directory, delete),
+                None => {
+                    return Ok(Outcome::Ambiguous {
+                        targets: vec![target.to_path_buf()],
+                    })
+                }
+            }
+            let target = target.to_path_buf();
+            if !relocate {
+                return Ok(Outcome::Moved {
+                    target,
+                    untouched: None,
+                });
+            }
+            let untouched = match target.exists() {
+                true => Some(Untouched::TargetExists),
+                false => untouched(&repo)?,
+            };
+            if untouched.is_some() {
+                return Ok(Outcome::Moved { target, untouched });
+            }
+            if !git.dry_run {
+                let url = git.clone_protocol.urls(repository)?[0];
+                repo.remote_set_url("origin", url)?;
+                drop(repo);
+                move_directory(directory, &target)?;
+            }
+            Ok(Outcome::Relocated { target })
+        }
+        _ => Ok(Outcome::Ambiguous {
+            targets: candidates
+                .iter()
+                .map(|(target, _)| target.to_path_buf())
+                .collect(),
+        }),
+    }
+}
+
+/// Report or delete a repository that is not on the server
+fn not_on_server(
+    git: &Git,
+    repo: Repository,
+    directory: &Path,
+    delete: bool,
+) -> errors::Result<Outcome> {
+    if !delete {
+        return Ok(Outcome::NotOnServer { untouched: None });
+    }
+    if let Some(untouched) = untouched(&repo)? {
+        return Ok(Outcome::NotOnServer {
+            untouched: Some(untouched),
+        });
+    }
+    if !git.dry_run {
+        drop(repo);
+        fs::remove_dir_all(directory).map_err(|source| errors::Error::FailedToPrune {
+            directory: directory.to_string_lossy().into_owned(),
+            source,
+        })?;
+    }
+    Ok(Outcome::Deleted)
+}
+
+/// Whether the local repository shares history with the head of the repository on the server,
+/// None when that cannot be told, offline or without local commits
+fn shares_history(
+    git: &Git,
+    credentials: &BitbucketCredentials,
+    repo: &Repository,
+    repository: &BitbucketRepository,
+) -> errors::Result<Option<bool>> {
+    let head = match repo.head().ok().and_then(|head| head.target()) {
+        Some(head) => head,
+        None => return Ok(None),
+    };
+    if git.inventory.offline() {
+        return Ok(None);
+    }
+
+    let url = git.clone_protocol.urls(repository)?[0];
+    let bar = git.progress.repository(&repository.name);
+    let result = git.retry.run(|| {
+        let mut remote = repo.remote_anonymous(url)?;
+        git.fetch(url, Some(credentials), &bar, |mut fo| {
+            remote.fetch(&["HEAD"], Some(&mut fo), None)
+        })
+    });
+    bar.finish_and_clear();
+    result?;
+
+    let fetched = repo.reference_to_annotated_commit(&repo.find_reference("FETCH_HEAD")?)?;
+    match repo.merge_base(head, fetched.id()) {
+        Ok(_) => Ok(Some(true)),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(Some(false)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Repositories with changes, including untracked files, stashes or unpushed commits must not be
+/// relocated or deleted
+fn untouched(repo: &Repository) -> errors::Result<Option<Untouched>> {
+    if !is_untouched_workspace(repo)? {
+        return Ok(Some(Untouched::Dirty));
+    }
+    if repo.find_reference("refs/stash").is_ok() {
+        return Ok(Some(Untouched::Stashed));
+    }
+    if has_unpushed_commits(repo)? {
+        return Ok(Some(Untouched::UnpushedCommits));
+    }
+
+    Ok(None)
+}
+
+/// Whether the workspace has no changes, not even untracked files
+fn is_untouched_workspace(repo: &Repository) -> errors::Result<bool> {
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true);
+    Ok(repo.statuses(Some(&mut options))?.is_empty())
+}
+
+/// Whether any local branch or a detached head contains commits not on a remote-tracking branch
+fn has_unpushed_commits(repo: &Repository) -> errors::Result<bool> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_glob("refs/heads/*")?;
+    if repo.head_detached()? {
+        revwalk.push_head()?;
+    }
+    revwalk.hide_glob("refs/remotes/*")?;
+
+    Ok(revwalk.next().is_some())
+}
+
+fn move_directory(directory: &Path, target: &Path) -> errors::Result<()> {
+    let map_err = |source| errors::Error::FailedToPrune {
+        directory: directory.to_string_lossy().into_owned(),
+        source,
+    };
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(map_err)?;
+    }
+    fs::rename(directory, target).map_err(map_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untracked_files_block_deletion() {
+        let directory =
+            std::env::temp_dir().join(format!("gitbucket-untracked-{}", std::process::id()));
+        let repo = Repository::init(&directory).unwrap();
+        assert_eq!(untouched(&repo).unwrap(), None);
+
+        fs::create_dir_all(directory.join("notes")).unwrap();
+        fs::write(directory.join("notes/todo.txt"), "work in progress").unwrap();
+        assert_eq!(untouched(&repo).unwrap(), Some(Untouched::Dirty));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn commit(repo: &Repository, message: &str) {
+        let signature = git2::Signature::now("gitbucket", "gitbucket@example.com").unwrap();
+        let tree = repo.treebuilder(None).unwrap().write().unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &[])
+            .unwrap();
+    }
+
+    #[test]
+    fn unrelated_repository_with_the_same_slug() {
+        let directory =
+            std::env::temp_dir().join(format!("gitbucket-same-slug-{}", std::process::id()));
+        let server = Repository::init(directory.join("B/api")).unwrap();
+        commit(&server, "unrelated");
+        let local = Repository::init(directory.join("A/api")).unwrap();
+        commit(&local, "deleted on the server");
+        let moved = Repository::clone(
+            &directory.join("B/api").to_string_lossy(),
+            directory.join("moved/api"),
+        )
+        .unwrap();
+
+        let git = Git::builder()
+            .root_directory(directory.to_string_lossy())
+            .dry_run(true)
+            .build();
+        let credentials = BitbucketCredentials::builder()
+            .username("alice")
+            .secret(crate::bitbucket::BitbucketSecret::Password(String::new()))
+            .build();
+        let repository = BitbucketRepository::builder()
+            .name("api")
+            .ssh_url(Some(directory.join("B/api").to_string_lossy().into_owned()))
+            .build();
+
+        let shares = |repo| shares_history(&git, &credentials, repo, &repository).unwrap();
+        assert_eq!(shares(&local), Some(false));
+        assert_eq!(shares(&moved), Some(true));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn hosts_of_the_clone_urls() {
+        let repository = BitbucketRepository::builder()
+            .name("api")
+            .ssh_url(Some("ssh://git@git.internal:7999/core/api.git".to_owned()))
+            .http_url(Some("https://git.internal/scm/core/api.git".to_owned()))
+            .build();
+        let hosts = server_hosts(&[("CORE".to_owned(), repository)]);
+
+        assert_eq!(hosts, HashSet::from(["git.internal".to_owned()]));
+    }
+}
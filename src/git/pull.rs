@@ -1,67 +1,91 @@
 use git2::{build::CheckoutBuilder, ErrorCode, Repository};
-use std::path::Path;
-use tracing::{debug, error, info, trace};
+use std::{
+    collections::HashSet,
+    path::Path,
+    time::{Duration, Instant},
+};
+use tracing::trace;
 
-use crate::{errors, git::Git};
+use crate::{
+    bitbucket::BitbucketCredentials,
+    errors,
+    git::{
+        head_branch, local_slug,
+        report::{Outcome, PendingReport, Report},
+        server_project, Git,
+    },
+};
 
-pub async fn run(git: Git, show_errors: bool) -> errors::Result<()> {
+pub async fn run(git: Git, credentials: Option<&BitbucketCredentials>) -> errors::Result<Report> {
+    let start = Instant::now();
     let existing_projects = git.get_existing_git_projects()?;
-    std::thread::scope(|s| {
-        existing_projects.iter().for_each(|project| {
-            let git = git.clone();
-            s.spawn(move || {
-                let span = tracing::info_span!("pull", "{}", project.to_string_lossy());
-                if let Err(e) = span.in_scope(|| fast_forward(&git, project, show_errors)) {
-                    error!("Error {:?}", e);
-                }
-            });
-        })
+    // Whether a repository became archived is only known from the last listing
+    let (archived, listed_ago) = git
+        .inventory
+        .last(Path::new(&git.root_directory))
+        .map(|inventory| (inventory.archived(), Some(inventory.age())))
+        .unwrap_or_default();
+    let overall = git.progress.overall(existing_projects.len());
+    let repositories = git.for_each(&existing_projects, |project| {
+        let span = tracing::info_span!("pull", "{}", project.to_string_lossy());
+        let report = PendingReport::new(&git, project).run(|report| {
+            span.in_scope(|| {
+                fast_forward(&git, credentials, project, &archived, listed_ago, report)
+            })
+        });
+        overall.inc(1);
+        report
     });
+    overall.finish_and_clear();
 
-    Ok(())
+    Ok(Report::new(&git, repositories, start))
 }
 
-fn fast_forward(git: &Git, directory: &Path, show_errors: bool) -> errors::Result<()> {
+fn fast_forward(
+    git: &Git,
+    credentials: Option<&BitbucketCredentials>,
+    directory: &Path,
+    archived: &HashSet<(String, String)>,
+    listed_ago: Option<Duration>,
+    report: &mut PendingReport,
+) -> errors::Result<Outcome> {
     trace!("Checking repo");
-    let repo = Repository::open(&directory)?;
-    if !Git::is_clean(&repo)? {
-        if show_errors {
-            debug!("Repository not clean");
-        }
-        return Ok(());
+    let repo = Repository::open(directory)?;
+    let (project, _) = git.project_and_name(directory);
+    let (project, separate) = server_project(&project);
+    if separate || archived.contains(&(project.to_owned(), local_slug(&repo, directory))) {
+        report.branch = head_branch(&repo);
+        return Ok(Outcome::Archived {
+            separate,
+            listed_ago: listed_ago.filter(|_| !separate),
+        });
+    }
+    let clean = Git::is_clean(&repo)?;
+    report.clean = Some(clean);
+    if !clean {
+        return Ok(Outcome::SkippedDirty);
     }
 
     let branch = match branch(&repo) {
         Ok(branch) => branch,
-        Err(e) => {
-            if let errors::Error::FailedGitOperation(e) = e {
-                if e.code() != ErrorCode::UnbornBranch {
-                    error!("Branch not found - {}", e);
-                }
-            }
-            return Ok(());
+        Err(errors::Error::FailedGitOperation(e)) if e.code() != ErrorCode::UnbornBranch => {
+            return Err(errors::Error::FailedGitOperation(e))
         }
+        Err(_) => return Ok(Outcome::SkippedDetached),
     };
+    report.branch = Some(branch.clone());
 
-    if check_path(git, &repo, &branch).is_err() {
-        return Ok(());
-    }
+    check_path(git, credentials, &repo, directory, &branch)?;
 
-    let fetch_head = match repo.find_reference("FETCH_HEAD") {
-        Ok(head) => head,
-        Err(e) => {
-            error!("Error fetching head - {}", e);
-            return Ok(());
-        }
-    };
+    let fetch_head = repo.find_reference("FETCH_HEAD")?;
 
     let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
     let (analysis, _) = repo.merge_analysis(&[&fetch_commit])?;
     if analysis.is_up_to_date() {
         trace!("up to date");
-        Ok(())
+        Ok(Outcome::UpToDate)
     } else if analysis.is_fast_forward() {
-        info!("fast-forwarding");
+        trace!("fast-forwarding");
         if !git.dry_run {
             let refname = format!("refs/heads/{}", &branch);
             let mut reference = repo.find_reference(&refname)?;
@@ -70,34 +94,35 @@ fn fast_forward(git: &Git, directory: &Path, show_errors: bool) -> errors::Resul
             repo.checkout_head(Some(CheckoutBuilder::default().force()))
                 .map_err(errors::Error::FailedGitOperation)?;
         }
-        Ok(())
+        Ok(Outcome::FastForwarded)
     } else {
-        debug!("Can't fast-forward");
-        Ok(())
+        trace!("Can't fast-forward");
+        Ok(Outcome::SkippedDiverged)
     }
 }
 
-const MAX_RETRIES: usize = 5;
-
-fn check_path(git: &Git, repo: &Repository, branch: &str) -> errors::Result<()> {
-    for _ in 1..MAX_RETRIES {
-        let result =
-            repo.find_remote("origin")?
-                .fetch(&[branch], Some(&mut git.fetch_options()), None);
-        match result {
-            Ok(_) => return Ok(()),
-            Err(e) => {
-                if e.code() != ErrorCode::UnbornBranch
-                    && !e.message().contains("failed to start SSH session")
-                {
-                    error!("Error fetching path - {}", e);
-                    return Err(errors::Error::FailedGitOperation(e));
-                }
-            }
-        }
+/// Fetch the branch from origin, retrying transient failures
+fn check_path(
+    git: &Git,
+    credentials: Option<&BitbucketCredentials>,
+    repo: &Repository,
+    directory: &Path,
+    branch: &str,
+) -> errors::Result<()> {
+    let (project, name) = git.project_and_name(directory);
+    let bar = git.progress.repository(&format!("{}/{}", project, name));
+    let result = git.retry.run(|| {
+        let mut remote = repo.find_remote("origin")?;
+        let url = remote.url().unwrap_or_default().to_owned();
+        git.fetch(&url, credentials, &bar, |mut fo| {
+            remote.fetch(&[branch], Some(&mut fo), None)
+        })
+    });
+    bar.finish_and_clear();
+    match result {
+        Err(errors::Error::FailedGitOperation(e)) if e.code() == ErrorCode::UnbornBranch => Ok(()),
+        result => result,
     }
-
-    Ok(())
 }
 
 fn branch(repository: &Repository) -> errors::Result<String> {
@@ -0,0 +1,303 @@
+//! This module contains the reports returned by the commands, with an outcome per repository
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    errors,
+    git::{pool, Git},
+};
+
+/// The result of a command
+#[derive(Debug)]
+pub struct Report {
+    /// Whether the command only showed what it would do
+    pub dry_run: bool,
+    /// The repositories, sorted by path
+    pub repositories: Vec<RepositoryReport>,
+    /// The duration of the whole command
+    pub duration: Duration,
+}
+
+impl Report {
+    /// A report of the repositories, sorted by path
+    pub(crate) fn new(
+        git: &Git,
+        mut repositories: Vec<RepositoryReport>,
+        start: Instant,
+    ) -> Report {
+        repositories.sort_by(|a, b| a.path.cmp(&b.path));
+        Report {
+            dry_run: git.dry_run,
+            repositories,
+            duration: start.elapsed(),
+        }
+    }
+
+    /// The repositories for which the command failed
+    pub fn failures(&self) -> impl Iterator<Item = &RepositoryReport> {
+        self.repositories
+            .iter()
+            .filter(|repository| repository.outcome.error().is_some())
+    }
+}
+
+/// The result of a command for a single repository
+#[derive(Debug)]
+pub struct RepositoryReport {
+    pub path: PathBuf,
+    pub project: String,
+    pub repository: String,
+    /// The branch the repository is on, when known
+    pub branch: Option<String>,
+    /// Whether the workspace has no changes, when checked
+    pub clean: Option<bool>,
+    pub outcome: Outcome,
+    pub duration: Duration,
+}
+
+/// A repository on the server with its local presence, the result of the list command
+#[derive(Debug)]
+pub struct ListedRepository {
+    pub project: String,
+    pub repository: String,
+    /// The local path, in the project directory of a selected repository
+    pub path: PathBuf,
+    /// The url a new clone uses
+    pub clone_url: Option<String>,
+    pub default_branch: Option<String>,
+    pub description: Option<String>,
+    /// Archived on the server
+    pub archived: bool,
+    pub presence: Presence,
+    /// The size of the local clone in bytes
+    pub local_size: Option<u64>,
+}
+
+/// Whether a repository on the server is cloned locally
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Presence {
+    Cloned,
+    /// Selected but not cloned
+    Missing,
+    Excluded(Exclusion),
+}
+
+impl Presence {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Presence::Cloned => "cloned",
+            Presence::Missing => "missing",
+            Presence::Excluded(_) => "excluded",
+        }
+    }
+}
+
+/// Why a repository on the server is not cloned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exclusion {
+    /// Excluded by the selected projects/repositories
+    Selection,
+    /// A personal repository of a user that is not included
+    Personal,
+    /// Archived on the server
+    Archived,
+}
+
+/// Why a repository is not relocated or deleted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Untouched {
+    /// Changes in the workspace
+    Dirty,
+    /// Commits that are not on a remote-tracking branch
+    UnpushedCommits,
+    /// Changes saved with git stash
+    Stashed,
+    /// The new location already exists
+    TargetExists,
+}
+
+/// The outcome of a command for a single repository
+#[derive(Debug)]
+pub enum Outcome {
+    /// Cloned from the server
+    Cloned { url: String },
+    /// Already cloned
+    Existing,
+    /// Not cloned
+    Excluded(Exclusion),
+    /// Fast-forwarded to the remote branch
+    FastForwarded,
+    /// Already up to date with the remote branch
+    UpToDate,
+    /// Not pulled because of changes in the workspace
+    SkippedDirty,
+    /// Not pulled because the local and remote branch diverged
+    SkippedDiverged,
+    /// Skipped because the head is detached or unborn
+    SkippedDetached,
+    /// Not pulled because it is archived on the server, `separate` when it is in the archived
+    /// directory, else how long ago the listing telling so was made
+    Archived {
+        separate: bool,
+        listed_ago: Option<Duration>,
+    },
+    /// No changes in the workspace
+    Clean,
+    /// Changes in the workspace
+    Dirty,
+    /// On a main branch
+    Main,
+    /// On a feature branch
+    Featured { default_branch: Option<String> },
+    /// Not found on the server, untouched when it should have been deleted
+    NotOnServer { untouched: Option<Untouched> },
+    /// Deleted because it no longer exists on the server
+    Deleted,
+    /// Moved to another project on the server, untouched when it should have been relocated
+    Moved {
+        target: PathBuf,
+        untouched: Option<Untouched>,
+    },
+    /// Relocated to the project it moved to
+    Relocated { target: PathBuf },
+    /// Moved to one of several repositories on the server
+    Ambiguous { targets: Vec<PathBuf> },
+    /// The command failed
+    Failed(errors::Error),
+}
+
+impl Outcome {
+    /// A stable name of the outcome, e.g. "fast-forwarded"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Cloned { .. } => "cloned",
+            Outcome::Existing => "existing",
+            Outcome::Excluded(_) => "excluded",
+            Outcome::FastForwarded => "fast-forwarded",
+            Outcome::UpToDate => "up-to-date",
+            Outcome::SkippedDirty => "skipped-dirty",
+            Outcome::SkippedDiverged => "skipped-diverged",
+            Outcome::SkippedDetached => "skipped-detached",
+            Outcome::Archived { .. } => "archived",
+            Outcome::Clean => "clean",
+            Outcome::Dirty => "dirty",
+            Outcome::Main => "main",
+            Outcome::Featured { .. } => "featured",
+            Outcome::NotOnServer { .. } => "not-on-server",
+            Outcome::Deleted => "deleted",
+            Outcome::Moved { .. } => "moved",
+            Outcome::Relocated { .. } => "relocated",
+            Outcome::Ambiguous { .. } => "ambiguous",
+            Outcome::Failed(_) => "failed",
+        }
+    }
+
+    /// The error when the command failed
+    pub fn error(&self) -> Option<&errors::Error> {
+        match self {
+            Outcome::Failed(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// A repository that is being checked by a command
+pub(crate) struct PendingReport {
+    path: PathBuf,
+    project: String,
+    repository: String,
+    pub(crate) branch: Option<String>,
+    pub(crate) clean: Option<bool>,
+    start: Instant,
+}
+
+impl PendingReport {
+    /// Start checking the repository in the given directory
+    pub(crate) fn new(git: &Git, directory: &Path) -> PendingReport {
+        let (project, repository) = git.project_and_name(directory);
+        PendingReport {
+            path: directory.to_path_buf(),
+            project,
+            repository,
+            branch: None,
+            clean: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Check the repository, a panic becomes a failed outcome like an error instead of ending the
+    /// command
+    pub(crate) fn run(
+        mut self,
+        check: impl FnOnce(&mut PendingReport) -> errors::Result<Outcome>,
+    ) -> RepositoryReport {
+        let result = pool::catch(|| check(&mut self));
+        self.finish(result)
+    }
+
+    /// The report with the outcome, or the error as a failed outcome
+    pub(crate) fn finish(self, result: errors::Result<Outcome>) -> RepositoryReport {
+        RepositoryReport {
+            path: self.path,
+            project: self.project,
+            repository: self.repository,
+            branch: self.branch,
+            clean: self.clean,
+            outcome: result.unwrap_or_else(Outcome::Failed),
+            duration: self.start.elapsed(),
+        }
+    }
+}
+
+/// The error message including its sources, e.g. "General git2 error: failed to resolve address"
+pub fn error_message(error: &errors::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git() -> Git {
+        Git::builder().root_directory("/src").dry_run(false).build()
+    }
+
+    #[test]
+    fn report_sorted_by_path() {
+        let git = git();
+        let web = PendingReport::new(&git, Path::new("/src/TOOLS/web")).finish(Ok(Outcome::Clean));
+        let mut api = PendingReport::new(&git, Path::new("/src/CORE/api"));
+        api.branch = Some("main".to_owned());
+        let api = api.finish(Ok(Outcome::FastForwarded));
+        let report = Report::new(&git, vec![web, api], Instant::now());
+
+        assert_eq!(report.repositories[0].project, "CORE");
+        assert_eq!(report.repositories[0].repository, "api");
+        assert_eq!(report.repositories[0].branch.as_deref(), Some("main"));
+        assert_eq!(report.repositories[0].outcome.name(), "fast-forwarded");
+        assert_eq!(report.failures().count(), 0);
+    }
+
+    #[test]
+    fn failed_outcome_with_source() {
+        let error = git2::Error::from_str("failed to resolve address");
+        let report = PendingReport::new(&git(), Path::new("/src/CORE/api"))
+            .finish(Err(errors::Error::FailedGitOperation(error)));
+
+        assert_eq!(report.outcome.name(), "failed");
+        assert_eq!(
+            report.outcome.error().map(error_message).as_deref(),
+            Some("General git2 error: failed to resolve address")
+        );
+    }
+}
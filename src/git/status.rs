@@ -1,32 +1,38 @@
 use git2::Repository;
-use std::path::Path;
-use tracing::{error, info, trace};
+use std::{path::Path, time::Instant};
+use tracing::trace;
 
-use crate::{errors, git::Git};
+use crate::{
+    errors,
+    git::{
+        head_branch,
+        report::{Outcome, PendingReport, Report},
+        Git,
+    },
+};
 
-pub async fn run(git: Git) -> errors::Result<()> {
+pub async fn run(git: Git) -> errors::Result<Report> {
+    let start = Instant::now();
     let existing_projects = git.get_existing_git_projects()?;
-    std::thread::scope(|s| {
-        existing_projects.iter().for_each(|directory| {
-            s.spawn(move || {
-                let span = tracing::info_span!("status", "{}", directory.to_string_lossy());
-                if let Err(e) = span.in_scope(|| status(directory)) {
-                    error!("Error {:?}", e);
-                }
-            });
-        });
+    let repositories = git.for_each(&existing_projects, |directory| {
+        let span = tracing::info_span!("status", "{}", directory.to_string_lossy());
+        PendingReport::new(&git, directory)
+            .run(|report| span.in_scope(|| status(directory, report)))
     });
 
-    Ok(())
+    Ok(Report::new(&git, repositories, start))
 }
 
-fn status(directory: &Path) -> errors::Result<()> {
+fn status(directory: &Path, report: &mut PendingReport) -> errors::Result<Outcome> {
     trace!("Checking directory");
 
     let repo = Repository::open(directory)?;
-    if !Git::is_clean(&repo)? {
-        info!("Directory is dirty");
-    }
+    report.branch = head_branch(&repo);
+    let clean = Git::is_clean(&repo)?;
+    report.clean = Some(clean);
 
-    Ok(())
+    Ok(match clean {
+        true => Outcome::Clean,
+        false => Outcome::Dirty,
+    })
 }
@@ -1,3 +1,6 @@
 pub mod bitbucket;
 pub mod errors;
 pub mod git;
+pub mod network;
+pub mod provider;
+pub mod retry;
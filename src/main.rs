@@ -1,42 +1,136 @@
 //! Gitbucket is a commandline tool to make it easy to mirror Bitbucket locally
 
 mod cli;
+mod config;
+mod exit;
+#[cfg(test)]
+mod fixtures;
+mod output;
 
 use std::env;
+use std::io;
+use std::process::ExitCode;
 use std::str::FromStr;
-use tracing::info;
 use tracing_subscriber::EnvFilter;
 
+use crate::output::OutputFormat;
+
 /// The asynchronous (Tokio) main method
 #[tokio::main]
-async fn main() -> eyre::Result<()> {
-    let start = std::time::Instant::now();
+async fn main() -> ExitCode {
+    #[cfg(windows)]
     let _ansi_support = ansi_term::enable_ansi_support();
 
+    match run().await {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit::error_code(&e))
+        }
+    }
+}
+
+/// Run the command, returning the exit code
+async fn run() -> eyre::Result<u8> {
+    let (command, options) = cli::SubCommand::from_arguments()?;
+    let output = options.output;
+
+    // Keep stdout clean for the results when they are machine-readable
     let log_level = env::var("RUST_LOG").unwrap_or_else(|_| String::from("INFO"));
     let filter = EnvFilter::from_str(&log_level)?;
-    tracing_subscriber::fmt()
+    let subscriber = tracing_subscriber::fmt()
         .without_time()
-        .with_env_filter(filter)
-        .init();
+        .with_env_filter(filter);
+    let progress = options.progress.clone();
+    match output {
+        OutputFormat::Text => subscriber
+            .with_writer(move || progress.writer(io::stdout()))
+            .init(),
+        _ => subscriber
+            .with_writer(move || progress.writer(io::stderr()))
+            .init(),
+    }
 
-    let command = cli::SubCommand::from_arguments()?;
-    match command {
+    let (report, verbose, summary) = match command {
         cli::SubCommand::Clone {
             git,
-            bitbucket_root_url,
+            provider,
             credentials,
-            exclusions,
+            personal,
         } => {
-            git.clone_command(&bitbucket_root_url, &credentials, exclusions)
-                .await?
+            let report = git
+                .clone_command(provider.as_ref(), &credentials, personal)
+                .await?;
+            (report, false, output::CLONE_SUMMARY)
         }
-        cli::SubCommand::Featured { git, show_main } => git.featured_command(show_main).await?,
-        cli::SubCommand::Pull { git, show_errors } => git.pull_command(show_errors).await?,
-        cli::SubCommand::Status { git } => git.status_command().await?,
-    }
-
-    info!("Finished in {}ms", start.elapsed().as_millis());
+        cli::SubCommand::ConfigShow { profile } => {
+            match output {
+                OutputFormat::Text => print!("{}", profile),
+                _ => output.print_value(&profile.masked())?,
+            }
+            return Ok(exit::SUCCESS);
+        }
+        cli::SubCommand::List {
+            git,
+            provider,
+            credentials,
+            personal,
+            projects,
+            selected,
+            default_branches,
+        } => {
+            let listed = git
+                .list_command(
+                    provider.as_ref(),
+                    &credentials,
+                    personal,
+                    &projects,
+                    selected,
+                    default_branches,
+                )
+                .await?;
+            output.print_listing(&listed)?;
+            output.print_listing_summary(&listed)?;
+            return Ok(exit::SUCCESS);
+        }
+        cli::SubCommand::Prune {
+            git,
+            provider,
+            credentials,
+            personal,
+            relocate,
+            delete,
+        } => {
+            let report = git
+                .prune_command(provider.as_ref(), &credentials, personal, relocate, delete)
+                .await?;
+            (report, false, output::PRUNE_SUMMARY)
+        }
+        cli::SubCommand::Featured {
+            git,
+            show_main,
+            remote,
+        } => {
+            let remote = remote.as_ref().map(|(provider, credentials, personal)| {
+                (provider.as_ref(), credentials, personal)
+            });
+            let report = git.featured_command(remote).await?;
+            (report, show_main, output::FEATURED_SUMMARY)
+        }
+        cli::SubCommand::Pull {
+            git,
+            show_errors,
+            credentials,
+        } => {
+            let report = git.pull_command(credentials.as_ref()).await?;
+            (report, show_errors, output::PULL_SUMMARY)
+        }
+        cli::SubCommand::Status { git } => {
+            (git.status_command().await?, false, output::STATUS_SUMMARY)
+        }
+    };
+    output.print_report(&report, verbose)?;
+    output.print_summary(&report, summary)?;
 
-    Ok(())
+    Ok(exit::report_code(&report, &options.fail_on))
 }
@@ -0,0 +1,625 @@
+//! This module contains the rendering of command reports as log lines or for scripts and dashboards.
+
+use clap::ArgEnum;
+use gitbucket::git::report::{
+    self, Exclusion, ListedRepository, Outcome, Presence, Report, RepositoryReport, Untouched,
+};
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    iter,
+    path::Path,
+    time::Duration,
+};
+use tracing::{error, info};
+
+/// The outcomes counted in the summary of a command, by name with their label
+pub type Summary = &'static [(&'static str, &'static str)];
+
+pub const CLONE_SUMMARY: Summary = &[
+    ("cloned", "cloned"),
+    ("existing", "existing"),
+    ("excluded", "excluded"),
+    ("failed", "failed"),
+];
+pub const FEATURED_SUMMARY: Summary = &[
+    ("featured", "on a feature branch"),
+    ("main", "on a main branch"),
+    ("skipped-detached", "detached/unborn"),
+    ("failed", "failed"),
+];
+pub const PRUNE_SUMMARY: Summary = &[
+    ("not-on-server", "not on the server"),
+    ("deleted", "deleted"),
+    ("moved", "moved"),
+    ("relocated", "relocated"),
+    ("ambiguous", "moved, ambiguous"),
+    ("failed", "failed"),
+];
+pub const PULL_SUMMARY: Summary = &[
+    ("fast-forwarded", "fast-forwarded"),
+    ("up-to-date", "up to date"),
+    ("skipped-dirty", "skipped, dirty"),
+    ("skipped-diverged", "skipped, diverged"),
+    ("skipped-detached", "skipped, detached/unborn"),
+    ("archived", "archived on the server"),
+    ("failed", "failed"),
+];
+pub const STATUS_SUMMARY: Summary = &[("clean", "clean"), ("dirty", "dirty"), ("failed", "failed")];
+
+/// The format of the command results on stdout
+#[derive(ArgEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Log lines only
+    #[default]
+    Text,
+    /// A JSON array with a record per repository
+    Json,
+    /// A JSON record per repository on every line
+    Ndjson,
+    /// Comma-separated values with a header line and a record per repository
+    Csv,
+}
+
+/// The machine-readable record of a repository
+#[derive(Serialize)]
+struct Record<'a> {
+    path: String,
+    project: &'a str,
+    repository: &'a str,
+    branch: Option<&'a str>,
+    clean: Option<bool>,
+    action: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    targets: Vec<String>,
+    duration_ms: u128,
+    error: Option<String>,
+}
+
+impl<'a> From<&'a RepositoryReport> for Record<'a> {
+    fn from(report: &'a RepositoryReport) -> Self {
+        let targets = match &report.outcome {
+            Outcome::Moved { target, .. } | Outcome::Relocated { target } => vec![display(target)],
+            Outcome::Ambiguous { targets } => targets.iter().map(|t| display(t)).collect(),
+            _ => Vec::new(),
+        };
+        Record {
+            path: display(&report.path),
+            project: &report.project,
+            repository: &report.repository,
+            branch: report.branch.as_deref(),
+            clean: report.clean,
+            action: report.outcome.name(),
+            targets,
+            duration_ms: report.duration.as_millis(),
+            error: report.outcome.error().map(report::error_message),
+        }
+    }
+}
+
+impl Record<'_> {
+    const HEADER: &'static [&'static str] = &[
+        "path",
+        "project",
+        "repository",
+        "branch",
+        "clean",
+        "action",
+        "targets",
+        "duration_ms",
+        "error",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.path.clone(),
+            self.project.to_owned(),
+            self.repository.to_owned(),
+            self.branch.unwrap_or_default().to_owned(),
+            self.clean.map(|c| c.to_string()).unwrap_or_default(),
+            self.action.to_owned(),
+            self.targets.join(";"),
+            self.duration_ms.to_string(),
+            self.error.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+/// The machine-readable record of a repository on the server
+#[derive(Serialize)]
+struct ListingRecord<'a> {
+    project: &'a str,
+    repository: &'a str,
+    path: String,
+    clone_url: Option<&'a str>,
+    default_branch: Option<&'a str>,
+    presence: &'static str,
+    archived: bool,
+    local_size_bytes: Option<u64>,
+    description: Option<&'a str>,
+}
+
+impl<'a> From<&'a ListedRepository> for ListingRecord<'a> {
+    fn from(listed: &'a ListedRepository) -> Self {
+        ListingRecord {
+            project: &listed.project,
+            repository: &listed.repository,
+            path: display(&listed.path),
+            clone_url: listed.clone_url.as_deref(),
+            default_branch: listed.default_branch.as_deref(),
+            presence: listed.presence.name(),
+            archived: listed.archived,
+            local_size_bytes: listed.local_size,
+            description: listed.description.as_deref(),
+        }
+    }
+}
+
+impl ListingRecord<'_> {
+    const HEADER: &'static [&'static str] = &[
+        "project",
+        "repository",
+        "path",
+        "clone_url",
+        "default_branch",
+        "presence",
+        "archived",
+        "local_size_bytes",
+        "description",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.project.to_owned(),
+            self.repository.to_owned(),
+            self.path.clone(),
+            self.clone_url.unwrap_or_default().to_owned(),
+            self.default_branch.unwrap_or_default().to_owned(),
+            self.presence.to_owned(),
+            self.archived.to_string(),
+            self.local_size_bytes
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+            self.description.unwrap_or_default().to_owned(),
+        ]
+    }
+}
+
+impl OutputFormat {
+    /// Render the report of a command, `verbose` also shows the repositories that need no
+    /// attention as text
+    pub fn print_report(self, report: &Report, verbose: bool) -> serde_json::Result<()> {
+        match self {
+            OutputFormat::Text => report
+                .repositories
+                .iter()
+                .for_each(|repository| log_repository(report, repository, verbose)),
+            OutputFormat::Json => {
+                let records = report.repositories.iter().map(Record::from);
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&records.collect::<Vec<_>>())?
+                )
+            }
+            OutputFormat::Ndjson => {
+                for repository in &report.repositories {
+                    println!("{}", serde_json::to_string(&Record::from(repository))?);
+                }
+            }
+            OutputFormat::Csv => {
+                println!("{}", csv_line(Record::HEADER));
+                for repository in &report.repositories {
+                    println!("{}", csv_line(&Record::from(repository).fields()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Render the repositories on the server, a table as text
+    pub fn print_listing(self, listed: &[ListedRepository]) -> serde_json::Result<()> {
+        let records = listed.iter().map(ListingRecord::from);
+        match self {
+            OutputFormat::Text => print!("{}", table(listed)),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&records.collect::<Vec<_>>())?
+            ),
+            OutputFormat::Ndjson => {
+                for record in records {
+                    println!("{}", serde_json::to_string(&record)?);
+                }
+            }
+            OutputFormat::Csv => {
+                println!("{}", csv_line(ListingRecord::HEADER));
+                for record in records {
+                    println!("{}", csv_line(&record.fields()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the number of repositories on the server by presence, on stderr when stdout has the
+    /// records
+    pub fn print_listing_summary(self, listed: &[ListedRepository]) -> io::Result<()> {
+        match self {
+            OutputFormat::Text => write_listing_summary(&mut io::stdout().lock(), listed),
+            _ => write_listing_summary(&mut io::stderr().lock(), listed),
+        }
+    }
+
+    /// Print the counts of the outcomes and the failures, on stderr when stdout has the records
+    pub fn print_summary(self, report: &Report, summary: Summary) -> io::Result<()> {
+        match self {
+            OutputFormat::Text => write_summary(&mut io::stdout().lock(), report, summary),
+            OutputFormat::Json | OutputFormat::Ndjson | OutputFormat::Csv => {
+                write_summary(&mut io::stderr().lock(), report, summary)
+            }
+        }
+    }
+
+    /// Print a single value, such as the configuration, as JSON
+    pub fn print_value<T: Serialize>(self, value: &T) -> serde_json::Result<()> {
+        match self {
+            OutputFormat::Text | OutputFormat::Json | OutputFormat::Csv => {
+                println!("{}", serde_json::to_string_pretty(value)?)
+            }
+            OutputFormat::Ndjson => println!("{}", serde_json::to_string(value)?),
+        }
+        Ok(())
+    }
+}
+
+fn write_summary(out: &mut impl Write, report: &Report, summary: Summary) -> io::Result<()> {
+    let width = summary
+        .iter()
+        .map(|(_, label)| label.len())
+        .max()
+        .unwrap_or(0);
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Summary of {} repositories in {}ms{}",
+        report.repositories.len(),
+        report.duration.as_millis(),
+        if report.dry_run { " (dry run)" } else { "" }
+    )?;
+    for (name, label) in summary {
+        let count = report
+            .repositories
+            .iter()
+            .filter(|repository| repository.outcome.name() == *name)
+            .count();
+        writeln!(out, "  {:width$}  {:>5}", label, count, width = width)?;
+    }
+
+    let mut failures = report.failures().peekable();
+    if failures.peek().is_some() {
+        writeln!(out)?;
+        writeln!(out, "Failures")?;
+        for repository in failures {
+            let error = repository.outcome.error().map(report::error_message);
+            writeln!(
+                out,
+                "  {} - {}",
+                display(&repository.path),
+                error.unwrap_or_default()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_listing_summary(out: &mut impl Write, listed: &[ListedRepository]) -> io::Result<()> {
+    let count = |presence: fn(&Presence) -> bool| {
+        listed
+            .iter()
+            .filter(|repository| presence(&repository.presence))
+            .count()
+    };
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{} repositories on the server, {} cloned, {} missing, {} excluded",
+        listed.len(),
+        count(|p| *p == Presence::Cloned),
+        count(|p| *p == Presence::Missing),
+        count(|p| matches!(p, Presence::Excluded(_)))
+    )
+}
+
+/// The repositories on the server as a table with aligned columns
+fn table(listed: &[ListedRepository]) -> String {
+    let header = [
+        "PROJECT",
+        "REPOSITORY",
+        "PRESENCE",
+        "DEFAULT BRANCH",
+        "LOCAL SIZE",
+        "CLONE URL",
+        "DESCRIPTION",
+    ]
+    .map(String::from);
+    let rows = listed.iter().map(|repository| {
+        [
+            repository.project.clone(),
+            repository.repository.clone(),
+            repository.presence.name().to_owned(),
+            repository.default_branch.clone().unwrap_or_default(),
+            repository.local_size.map(human_size).unwrap_or_default(),
+            repository.clone_url.clone().unwrap_or_default(),
+            repository
+                .description
+                .as_deref()
+                .map(summary_line)
+                .unwrap_or_default(),
+        ]
+    });
+    let rows = iter::once(header).chain(rows).collect::<Vec<_>>();
+
+    let mut widths = [0; 7];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(column, (cell, width))| match column {
+                4 => format!("{:>width$}", cell, width = width),
+                _ => format!("{:width$}", cell, width = width),
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// The first line of a description, shortened to fit a table
+fn summary_line(description: &str) -> String {
+    const MAX_LENGTH: usize = 60;
+    let line = description.lines().next().unwrap_or_default().trim();
+    match line.chars().count() > MAX_LENGTH {
+        true => format!(
+            "{}...",
+            line.chars().take(MAX_LENGTH - 3).collect::<String>()
+        ),
+        false => line.to_owned(),
+    }
+}
+
+/// A size in bytes for people, e.g. "1.5 MiB"
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// A duration for people in its largest whole unit, e.g. "3h"
+fn human_age(age: Duration) -> String {
+    let seconds = age.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+/// A line of comma-separated values, quoting the values with a comma, quote or line break
+fn csv_line<S: AsRef<str>>(values: &[S]) -> String {
+    values
+        .iter()
+        .map(|value| {
+            let value = value.as_ref();
+            match value.contains([',', '"', '\n', '\r']) {
+                true => format!("\"{}\"", value.replace('"', "\"\"")),
+                false => value.to_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Log the outcome of a repository when it needs attention
+fn log_repository(report: &Report, repository: &RepositoryReport, verbose: bool) {
+    let path = display(&repository.path);
+    let branch = repository.branch.as_deref().unwrap_or_default();
+    let message = match &repository.outcome {
+        Outcome::Failed(e) => {
+            error!("{} - Error {}", path, report::error_message(e));
+            return;
+        }
+        Outcome::Existing | Outcome::UpToDate | Outcome::Clean => return,
+        Outcome::Excluded(_) if !report.dry_run => return,
+        Outcome::Main
+        | Outcome::SkippedDirty
+        | Outcome::SkippedDiverged
+        | Outcome::SkippedDetached
+        | Outcome::Archived { separate: true, .. }
+            if !verbose =>
+        {
+            return
+        }
+        Outcome::Cloned { url } => format!("cloning repository from {}", url),
+        Outcome::Excluded(Exclusion::Selection) => String::from("skipping - excluded"),
+        Outcome::Excluded(Exclusion::Personal) => {
+            String::from("skipping - personal repository not selected")
+        }
+        Outcome::Excluded(Exclusion::Archived) => String::from("skipping - archived"),
+        Outcome::Archived {
+            listed_ago: Some(age),
+            ..
+        } => format!(
+            "archived on the server as listed {} ago, not pulled",
+            human_age(*age)
+        ),
+        Outcome::Archived { .. } => String::from("archived on the server, not pulled"),
+        Outcome::FastForwarded => String::from("fast-forwarding"),
+        Outcome::SkippedDirty => String::from("repository not clean"),
+        Outcome::SkippedDiverged => String::from("can't fast-forward"),
+        Outcome::SkippedDetached => String::from("not on a branch"),
+        Outcome::Dirty => String::from("directory is dirty"),
+        Outcome::Main => format!("on branch {}", branch),
+        Outcome::Featured {
+            default_branch: Some(default_branch),
+        } => format!("on branch {}, default {}", branch, default_branch),
+        Outcome::Featured {
+            default_branch: None,
+        } => format!("on branch {}", branch),
+        Outcome::NotOnServer { untouched } => {
+            format!("not found on the server{}", leaving(untouched))
+        }
+        Outcome::Deleted => String::from("not found on the server, deleting"),
+        Outcome::Moved { target, untouched } => {
+            format!("moved to {}{}", display(target), leaving(untouched))
+        }
+        Outcome::Relocated { target } => format!("moved to {}, relocating", display(target)),
+        Outcome::Ambiguous { targets } if targets.len() == 1 => {
+            format!("possibly moved to {}", display(&targets[0]))
+        }
+        Outcome::Ambiguous { targets } => {
+            let targets = targets.iter().map(|t| display(t)).collect::<Vec<_>>();
+            format!("moved to one of {}", targets.join(", "))
+        }
+    };
+    info!("{} - {}", path, message);
+}
+
+/// Why a repository is left untouched, if it is
+fn leaving(untouched: &Option<Untouched>) -> &'static str {
+    match untouched {
+        None => "",
+        Some(Untouched::Dirty) => ", not clean, leaving untouched",
+        Some(Untouched::UnpushedCommits) => ", unpushed commits, leaving untouched",
+        Some(Untouched::Stashed) => ", stashed changes, leaving untouched",
+        Some(Untouched::TargetExists) => ", already exists, leaving untouched",
+    }
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{report, repository};
+    use gitbucket::errors;
+    use std::path::PathBuf;
+
+    #[test]
+    fn serialize_record() {
+        let report = repository(
+            "/src/CORE/api",
+            Outcome::Relocated {
+                target: PathBuf::from("/src/PLATFORM/api"),
+            },
+        );
+
+        assert_eq!(
+            serde_json::to_string(&Record::from(&report)).unwrap(),
+            r#"{"path":"/src/CORE/api","project":"CORE","repository":"api","branch":"main","clean":true,"action":"relocated","targets":["/src/PLATFORM/api"],"duration_ms":42,"error":null}"#
+        );
+    }
+
+    #[test]
+    fn summary_with_failures() {
+        let report = report(vec![
+            repository("/src/CORE/api", Outcome::FastForwarded),
+            repository("/src/CORE/web", Outcome::UpToDate),
+            repository("/src/CORE/cli", Outcome::UpToDate),
+            repository(
+                "/src/CORE/old",
+                Outcome::Failed(errors::Error::NoBranchFound),
+            ),
+        ]);
+        let mut out = Vec::new();
+        write_summary(&mut out, &report, PULL_SUMMARY).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "
+Summary of 4 repositories in 1234ms
+  fast-forwarded                1
+  up to date                    2
+  skipped, dirty                0
+  skipped, diverged             0
+  skipped, detached/unborn      0
+  archived on the server        0
+  failed                        1
+
+Failures
+  /src/CORE/old - no branch found
+"
+        );
+    }
+
+    #[test]
+    fn listing_table() {
+        let listed = vec![
+            ListedRepository {
+                project: "CORE".to_owned(),
+                repository: "api".to_owned(),
+                path: PathBuf::from("/src/CORE/api"),
+                clone_url: Some("ssh://git@host/core/api.git".to_owned()),
+                default_branch: Some("main".to_owned()),
+                description: Some("The public API\nSee the README".to_owned()),
+                archived: false,
+                presence: Presence::Cloned,
+                local_size: Some(1536),
+            },
+            ListedRepository {
+                project: "TOOLS".to_owned(),
+                repository: "cli".to_owned(),
+                path: PathBuf::from("/src/TOOLS/cli"),
+                clone_url: None,
+                default_branch: None,
+                description: None,
+                archived: true,
+                presence: Presence::Excluded(Exclusion::Selection),
+                local_size: None,
+            },
+        ];
+
+        assert_eq!(
+            table(&listed),
+            "\
+PROJECT  REPOSITORY  PRESENCE  DEFAULT BRANCH  LOCAL SIZE  CLONE URL                    DESCRIPTION
+CORE     api         cloned    main               1.5 KiB  ssh://git@host/core/api.git  The public API
+TOOLS    cli         excluded
+"
+        );
+    }
+
+    #[test]
+    fn ages_for_people() {
+        assert_eq!(human_age(Duration::from_secs(42)), "42s");
+        assert_eq!(human_age(Duration::from_secs(600)), "10m");
+        assert_eq!(human_age(Duration::from_secs(3 * 3600 + 59)), "3h");
+        assert_eq!(human_age(Duration::from_secs(9 * 86400)), "9d");
+    }
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(
+            csv_line(&["CORE", "a, b", "say \"hi\"", ""]),
+            r#"CORE,"a, b","say ""hi""","#
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Query the repositories of Gitea organizations
+
+use serde::Deserialize;
+use ureq::Agent;
+
+use crate::{
+    bitbucket::{BitbucketCredentials, BitbucketRepository},
+    errors,
+    provider::{get, get_default_branch, next_page, Pages, Provider},
+};
+
+/// The page size used when listing repositories, Gitea's default maximum
+const PAGE_SIZE: usize = 50;
+
+/// The organizations on a Gitea instance
+#[derive(Clone, Debug)]
+pub struct Gitea {
+    pub root_url: String,
+    pub organizations: Vec<String>,
+    pub agent: Agent,
+}
+
+impl Provider for Gitea {
+    fn repository_pages<'a>(&'a self, credentials: &'a BitbucketCredentials) -> Pages<'a> {
+        let authorization = String::from(credentials);
+        let urls = self.organizations.iter().map(|organization| {
+            format!(
+                "{}/api/v1/orgs/{}/repos?limit={}&page=1",
+                self.root_url, organization, PAGE_SIZE
+            )
+        });
+
+        Pages::new(urls, move |url| {
+            let json: Vec<RemoteRepository> = get(&self.agent, url, &authorization)?
+                .into_json()
+                .map_err(errors::Error::FailedToParseJSON)?;
+            let next = match json.len() < PAGE_SIZE {
+                true => None,
+                false => next_page(url),
+            };
+
+            let page = json
+                .into_iter()
+                .map(|value| {
+                    let data = BitbucketRepository::builder()
+                        .slug(Some(value.name.clone()))
+                        .name(value.name)
+                        .id(Some(value.id.to_string()))
+                        .description(value.description.filter(|d| !d.is_empty()))
+                        .archived(value.archived)
+                        .public(!value.private)
+                        .ssh_url(value.ssh_url)
+                        .http_url(value.clone_url)
+                        .default_branch(value.default_branch)
+                        .build();
+                    (value.owner.login, data)
+                })
+                .collect();
+
+            Ok((page, next))
+        })
+    }
+
+    fn default_branch(
+        &self,
+        credentials: &BitbucketCredentials,
+        project: &str,
+        slug: &str,
+    ) -> errors::Result<Option<String>> {
+        let url = format!("{}/api/v1/repos/{}/{}", self.root_url, project, slug);
+        get_default_branch(&self.agent, &url, credentials)
+    }
+}
+
+/// A Remote Repository representation
+#[derive(Deserialize, Debug)]
+struct RemoteRepository {
+    id: u64,
+    name: String,
+    description: Option<String>,
+    #[serde(default)]
+    archived: bool,
+    #[serde(default)]
+    private: bool,
+    ssh_url: Option<String>,
+    clone_url: Option<String>,
+    default_branch: Option<String>,
+    owner: RemoteOwner,
+}
+
+#[derive(Deserialize, Debug)]
+struct RemoteOwner {
+    login: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_repositories() {
+        let json = r#"
+        [
+            {
+                "id": 7,
+                "name": "infra",
+                "full_name": "ops/infra",
+                "owner": {
+                    "id": 3,
+                    "login": "ops",
+                    "username": "ops"
+                },
+                "ssh_url": "git@gitea.example.com:ops/infra.git",
+                "clone_url": "https://gitea.example.com/ops/infra.git",
+                "archived": false
+            }
+        ]
+        "#;
+        let result: Vec<RemoteRepository> = serde_json::from_str(json).unwrap();
+
+        assert_eq!(result.len(), 1);
+
+        let result: &RemoteRepository = result.first().unwrap();
+
+        assert_eq!(result.name, "infra");
+        assert_eq!(result.id, 7);
+        assert_eq!(
+            result.ssh_url.as_deref(),
+            Some("git@gitea.example.com:ops/infra.git")
+        );
+        assert_eq!(
+            result.clone_url.as_deref(),
+            Some("https://gitea.example.com/ops/infra.git")
+        );
+        assert_eq!(result.owner.login, "ops");
+    }
+}
@@ -0,0 +1,137 @@
+//! Query the repositories of GitHub organizations
+
+use serde::Deserialize;
+use ureq::Agent;
+
+use crate::{
+    bitbucket::{BitbucketCredentials, BitbucketRepository},
+    errors,
+    provider::{get, get_default_branch, next_link, Pages, Provider},
+};
+
+/// The organizations on a GitHub (Enterprise) instance
+#[derive(Clone, Debug)]
+pub struct GitHub {
+    pub api_url: String,
+    pub organizations: Vec<String>,
+    pub agent: Agent,
+}
+
+impl GitHub {
+    /// The API root url of github.com
+    pub const API_URL: &'static str = "https://api.github.com";
+}
+
+impl Provider for GitHub {
+    fn repository_pages<'a>(&'a self, credentials: &'a BitbucketCredentials) -> Pages<'a> {
+        let authorization = String::from(credentials);
+        let urls = self.organizations.iter().map(|organization| {
+            format!("{}/orgs/{}/repos?per_page=100", self.api_url, organization)
+        });
+
+        Pages::new(urls, move |url| {
+            let response = get(&self.agent, url, &authorization)?;
+            let next = next_link(response.header("Link"));
+
+            let json: Vec<RemoteRepository> = response
+                .into_json()
+                .map_err(errors::Error::FailedToParseJSON)?;
+            let page = json
+                .into_iter()
+                .map(|value| {
+                    let data = BitbucketRepository::builder()
+                        .slug(Some(value.name.clone()))
+                        .name(value.name)
+                        .id(Some(value.id.to_string()))
+                        .description(value.description)
+                        .archived(value.archived)
+                        .forkable(value.allow_forking)
+                        .public(!value.private)
+                        .ssh_url(value.ssh_url)
+                        .http_url(value.clone_url)
+                        .default_branch(value.default_branch)
+                        .build();
+                    (value.owner.login, data)
+                })
+                .collect();
+
+            Ok((page, next))
+        })
+    }
+
+    fn default_branch(
+        &self,
+        credentials: &BitbucketCredentials,
+        project: &str,
+        slug: &str,
+    ) -> errors::Result<Option<String>> {
+        let url = format!("{}/repos/{}/{}", self.api_url, project, slug);
+        get_default_branch(&self.agent, &url, credentials)
+    }
+}
+
+/// A Remote Repository representation
+#[derive(Deserialize, Debug)]
+struct RemoteRepository {
+    id: u64,
+    name: String,
+    description: Option<String>,
+    #[serde(default)]
+    archived: bool,
+    #[serde(default)]
+    private: bool,
+    allow_forking: Option<bool>,
+    ssh_url: Option<String>,
+    clone_url: Option<String>,
+    default_branch: Option<String>,
+    owner: RemoteOwner,
+}
+
+#[derive(Deserialize, Debug)]
+struct RemoteOwner {
+    login: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_repositories() {
+        let json = r#"
+        [
+            {
+                "id": 1296269,
+                "name": "hello-world",
+                "full_name": "octocat/hello-world",
+                "owner": {
+                    "login": "octocat",
+                    "id": 1
+                },
+                "private": false,
+                "ssh_url": "git@github.com:octocat/hello-world.git",
+                "clone_url": "https://github.com/octocat/hello-world.git",
+                "archived": false
+            }
+        ]
+        "#;
+        let result: Vec<RemoteRepository> = serde_json::from_str(json).unwrap();
+
+        assert_eq!(result.len(), 1);
+
+        let result: &RemoteRepository = result.first().unwrap();
+
+        assert_eq!(result.name, "hello-world");
+        assert_eq!(result.id, 1296269);
+        assert!(!result.private);
+        assert_eq!(
+            result.ssh_url.as_deref(),
+            Some("git@github.com:octocat/hello-world.git")
+        );
+        assert_eq!(
+            result.clone_url.as_deref(),
+            Some("https://github.com/octocat/hello-world.git")
+        );
+        assert_eq!(result.owner.login, "octocat");
+    }
+}
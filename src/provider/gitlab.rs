@@ -0,0 +1,158 @@
+//! Query the projects of GitLab groups, including their subgroups
+
+use serde::Deserialize;
+use ureq::Agent;
+
+use crate::{
+    bitbucket::{BitbucketCredentials, BitbucketRepository},
+    errors,
+    provider::{get, get_default_branch, next_page, Pages, Provider},
+};
+
+/// The groups on a GitLab instance
+#[derive(Clone, Debug)]
+pub struct GitLab {
+    pub root_url: String,
+    pub groups: Vec<String>,
+    pub agent: Agent,
+}
+
+impl GitLab {
+    /// The root url of gitlab.com
+    pub const ROOT_URL: &'static str = "https://gitlab.com";
+    /// The directories a project of a group spans at most, a group with 20 levels of subgroups
+    pub const MAX_GROUP_DEPTH: usize = 21;
+}
+
+impl Provider for GitLab {
+    fn repository_pages<'a>(&'a self, credentials: &'a BitbucketCredentials) -> Pages<'a> {
+        let authorization = String::from(credentials);
+        let urls = self.groups.iter().map(|group| {
+            format!(
+                "{}/api/v4/groups/{}/projects?include_subgroups=true&per_page=100&page=1",
+                self.root_url,
+                group.replace('/', "%2F"),
+            )
+        });
+
+        Pages::new(urls, move |url| {
+            let response = get(&self.agent, url, &authorization)?;
+            let next = match response.header("X-Next-Page") {
+                Some(next) if !next.is_empty() => next_page(url),
+                _ => None,
+            };
+
+            let json: Vec<RemoteProject> = response
+                .into_json()
+                .map_err(errors::Error::FailedToParseJSON)?;
+            let page = json
+                .into_iter()
+                .map(|value| {
+                    let data = BitbucketRepository::builder()
+                        .slug(Some(value.path.clone()))
+                        .name(value.path)
+                        .id(Some(value.id.to_string()))
+                        .description(value.description.filter(|d| !d.is_empty()))
+                        .project_name(Some(value.namespace.name))
+                        .archived(value.archived)
+                        .forkable(value.forking_access_level.map(|level| level != "disabled"))
+                        .public(value.visibility.as_deref() == Some("public"))
+                        .ssh_url(value.ssh_url_to_repo)
+                        .http_url(value.http_url_to_repo)
+                        .default_branch(value.default_branch)
+                        .build();
+                    (value.namespace.full_path, data)
+                })
+                .collect();
+
+            Ok((page, next))
+        })
+    }
+
+    fn default_branch(
+        &self,
+        credentials: &BitbucketCredentials,
+        project: &str,
+        slug: &str,
+    ) -> errors::Result<Option<String>> {
+        let url = format!(
+            "{}/api/v4/projects/{}%2F{}",
+            self.root_url,
+            project.replace('/', "%2F"),
+            slug
+        );
+        get_default_branch(&self.agent, &url, credentials)
+    }
+}
+
+/// A Remote Project representation, GitLab's name for a repository
+#[derive(Deserialize, Debug)]
+struct RemoteProject {
+    id: u64,
+    path: String,
+    description: Option<String>,
+    #[serde(default)]
+    archived: bool,
+    /// One of "private", "internal" and "public"
+    visibility: Option<String>,
+    /// One of "disabled", "private" and "enabled"
+    forking_access_level: Option<String>,
+    ssh_url_to_repo: Option<String>,
+    http_url_to_repo: Option<String>,
+    default_branch: Option<String>,
+    namespace: RemoteNamespace,
+}
+
+/// The group or subgroup a project lives in
+#[derive(Deserialize, Debug)]
+struct RemoteNamespace {
+    name: String,
+    full_path: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_projects() {
+        let json = r#"
+        [
+            {
+                "id": 4,
+                "name": "Diaspora Client",
+                "path": "diaspora-client",
+                "path_with_namespace": "diaspora/clients/diaspora-client",
+                "ssh_url_to_repo": "git@gitlab.com:diaspora/clients/diaspora-client.git",
+                "http_url_to_repo": "https://gitlab.com/diaspora/clients/diaspora-client.git",
+                "namespace": {
+                    "id": 3,
+                    "name": "Clients",
+                    "path": "clients",
+                    "kind": "group",
+                    "full_path": "diaspora/clients"
+                },
+                "archived": false
+            }
+        ]
+        "#;
+        let result: Vec<RemoteProject> = serde_json::from_str(json).unwrap();
+
+        assert_eq!(result.len(), 1);
+
+        let result: &RemoteProject = result.first().unwrap();
+
+        assert_eq!(result.path, "diaspora-client");
+        assert_eq!(
+            result.ssh_url_to_repo.as_deref(),
+            Some("git@gitlab.com:diaspora/clients/diaspora-client.git")
+        );
+        assert_eq!(
+            result.http_url_to_repo.as_deref(),
+            Some("https://gitlab.com/diaspora/clients/diaspora-client.git")
+        );
+        assert_eq!(result.namespace.full_path, "diaspora/clients");
+        assert_eq!(result.namespace.name, "Clients");
+        assert_eq!(result.id, 4);
+    }
+}
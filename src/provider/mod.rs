@@ -0,0 +1,211 @@
+//! This module contains the hosting providers that can be mirrored locally.
+
+pub mod gitea;
+pub mod github;
+pub mod gitlab;
+
+use serde::Deserialize;
+use std::collections::VecDeque;
+use ureq::{Agent, Response};
+
+use crate::{
+    bitbucket::{self, BitbucketCredentials, BitbucketHost, BitbucketRepository},
+    errors,
+};
+
+/// The repositories on a page of a listing, with their project
+pub type Page = Vec<(String, BitbucketRepository)>;
+
+/// The pages of a repository listing, each page is fetched when it is needed. A page that
+/// failed is fetched again by the next call, so it can be retried.
+pub struct Pages<'a> {
+    urls: VecDeque<String>,
+    fetch: Box<FetchPage<'a>>,
+}
+
+/// Fetch the page at the url, returning the url of the next page
+type FetchPage<'a> = dyn FnMut(&str) -> errors::Result<(Page, Option<String>)> + Send + 'a;
+
+impl<'a> Pages<'a> {
+    /// The pages starting at the urls, fetching a page returns the url of the next page
+    pub fn new<F>(urls: impl IntoIterator<Item = String>, fetch: F) -> Pages<'a>
+    where
+        F: FnMut(&str) -> errors::Result<(Page, Option<String>)> + Send + 'a,
+    {
+        Pages {
+            urls: urls.into_iter().collect(),
+            fetch: Box::new(fetch),
+        }
+    }
+}
+
+impl Iterator for Pages<'_> {
+    type Item = errors::Result<Page>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let url = self.urls.pop_front()?;
+        match (self.fetch)(&url) {
+            Ok((page, next)) => {
+                if let Some(next) = next {
+                    self.urls.push_front(next);
+                }
+                Some(Ok(page))
+            }
+            Err(e) => {
+                self.urls.push_front(url);
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// A hosting provider listing its repositories per project, shared by the worker threads
+pub trait Provider: Send + Sync {
+    /// Query the repositories with their project, a page at a time
+    fn repository_pages<'a>(&'a self, credentials: &'a BitbucketCredentials) -> Pages<'a>;
+
+    /// Query the default branch of a repository, None when it has no default branch
+    fn default_branch(
+        &self,
+        credentials: &BitbucketCredentials,
+        project: &str,
+        slug: &str,
+    ) -> errors::Result<Option<String>>;
+}
+
+impl Provider for BitbucketHost {
+    fn repository_pages<'a>(&'a self, credentials: &'a BitbucketCredentials) -> Pages<'a> {
+        bitbucket::repository_pages(self, credentials, 250)
+    }
+
+    fn default_branch(
+        &self,
+        credentials: &BitbucketCredentials,
+        project: &str,
+        slug: &str,
+    ) -> errors::Result<Option<String>> {
+        bitbucket::default_branch(self, credentials, project, slug)
+    }
+}
+
+/// Do an authorized GET call, translating a 401 into invalid credentials
+pub(crate) fn get(agent: &Agent, url: &str, authorization: &str) -> errors::Result<Response> {
+    agent
+        .get(url)
+        .set("Authorization", authorization)
+        .call()
+        .map_err(|e| match e {
+            ureq::Error::Status(401, _) => errors::Error::InvalidUsernamePassword,
+            _ => errors::Error::FailedToDoHttpCall(Box::new(e)),
+        })
+}
+
+/// Do an authorized GET call, None when the resource is not found
+pub(crate) fn get_optional(
+    agent: &Agent,
+    url: &str,
+    authorization: &str,
+) -> errors::Result<Option<Response>> {
+    match get(agent, url, authorization) {
+        Ok(response) => Ok(Some(response)),
+        Err(errors::Error::FailedToDoHttpCall(e)) if matches!(*e, ureq::Error::Status(404, _)) => {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The default branch of a repository as returned by GitHub, GitLab and Gitea
+#[derive(Deserialize, Debug)]
+struct RemoteDefaultBranch {
+    default_branch: Option<String>,
+}
+
+/// Get the default branch from the repository at the url
+fn get_default_branch(
+    agent: &Agent,
+    url: &str,
+    credentials: &BitbucketCredentials,
+) -> errors::Result<Option<String>> {
+    let response = match get_optional(agent, url, &String::from(credentials))? {
+        Some(response) => response,
+        None => return Ok(None),
+    };
+    let json: RemoteDefaultBranch = response
+        .into_json()
+        .map_err(errors::Error::FailedToParseJSON)?;
+
+    Ok(json.default_branch)
+}
+
+/// Get the url with relation "next" from a RFC 8288 Link header
+fn next_link(link_header: Option<&str>) -> Option<String> {
+    link_header?.split(',').find_map(|link| {
+        let mut parts = link.split(';');
+        let url = parts.next()?.trim();
+        parts
+            .any(|param| param.trim() == r#"rel="next""#)
+            .then(|| url.trim_start_matches('<').trim_end_matches('>').to_owned())
+    })
+}
+
+/// The url of the next page of a listing paginated by a trailing `page` parameter
+fn next_page(url: &str) -> Option<String> {
+    let (base, page) = url.rsplit_once("page=")?;
+    let page = page.parse::<u32>().ok()?;
+    Some(format!("{}page={}", base, page + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_link_present() {
+        let header = r#"<https://api.github.com/organizations/1/repos?page=3>; rel="next", <https://api.github.com/organizations/1/repos?page=1>; rel="prev""#;
+
+        assert_eq!(
+            next_link(Some(header)),
+            Some("https://api.github.com/organizations/1/repos?page=3".to_owned())
+        );
+    }
+
+    #[test]
+    fn next_page_number() {
+        assert_eq!(
+            next_page("https://gitea.example.com/api/v1/orgs/ops/repos?limit=50&page=2"),
+            Some("https://gitea.example.com/api/v1/orgs/ops/repos?limit=50&page=3".to_owned())
+        );
+        assert_eq!(
+            next_page("https://gitea.example.com/api/v1/orgs/ops/repos"),
+            None
+        );
+    }
+
+    #[test]
+    fn failed_page_fetched_again() {
+        let mut calls = 0;
+        let mut pages = Pages::new(["1".to_owned(), "3".to_owned()], |url| {
+            calls += 1;
+            match (url, calls) {
+                ("1", _) => Ok((Vec::new(), Some("2".to_owned()))),
+                ("2", 2) => Err(errors::Error::HttpUrlMissing),
+                _ => Ok((Vec::new(), None)),
+            }
+        });
+
+        assert!(pages.next().unwrap().is_ok());
+        assert!(pages.next().unwrap().is_err());
+        assert!(pages.next().unwrap().is_ok());
+        assert!(pages.next().unwrap().is_ok());
+        assert!(pages.next().is_none());
+    }
+
+    #[test]
+    fn next_link_missing() {
+        let header = r#"<https://api.github.com/organizations/1/repos?page=1>; rel="prev""#;
+
+        assert_eq!(next_link(Some(header)), None);
+        assert_eq!(next_link(None), None);
+    }
+}
@@ -0,0 +1,161 @@
+//! This module contains the retry policy shared by clones, fetches and REST calls
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    thread,
+    time::Duration,
+};
+use tracing::warn;
+use typed_builder::TypedBuilder;
+
+use crate::{errors, git::report::error_message};
+
+/// The default number of retries after the first attempt
+pub const DEFAULT_RETRIES: u32 = 3;
+/// The default delay before the first retry in milliseconds
+pub const DEFAULT_BACKOFF_MS: u64 = 500;
+
+/// Retry transient failures with an exponential backoff and jitter
+#[derive(TypedBuilder, Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// The number of retries after the first attempt
+    #[builder(default = DEFAULT_RETRIES)]
+    retries: u32,
+    /// The delay before the first retry, doubled for every next retry
+    #[builder(default = Duration::from_millis(DEFAULT_BACKOFF_MS))]
+    backoff: Duration,
+    /// The maximum delay between retries
+    #[builder(default = Duration::from_secs(30))]
+    max_backoff: Duration,
+    /// The fraction of the delay that is randomized, so parallel retries are spread out
+    #[builder(default = 0.5)]
+    jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::builder().build()
+    }
+}
+
+impl RetryPolicy {
+    /// Run the operation, retrying as long as it fails with a transient error
+    pub fn run<T>(&self, mut operation: impl FnMut() -> errors::Result<T>) -> errors::Result<T> {
+        let mut retry = 0;
+        loop {
+            match operation() {
+                Err(e) if retry < self.retries && e.is_transient() => {
+                    retry += 1;
+                    let delay = self.delay(retry);
+                    warn!(
+                        "Retrying in {}ms ({} of {}) - {}",
+                        delay.as_millis(),
+                        retry,
+                        self.retries,
+                        error_message(&e)
+                    );
+                    thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The delay before a retry, the exponential backoff minus a random part of it
+    fn delay(&self, retry: u32) -> Duration {
+        let exponential = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(retry - 1))
+            .min(self.max_backoff);
+        exponential - exponential.mul_f64(self.jitter.clamp(0.0, 1.0) * random_fraction())
+    }
+}
+
+/// A random number between 0 and 1, from the randomly seeded standard hasher
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(retries: u32) -> RetryPolicy {
+        RetryPolicy::builder()
+            .retries(retries)
+            .backoff(Duration::ZERO)
+            .build()
+    }
+
+    fn network_error() -> errors::Error {
+        errors::Error::FailedGitOperation(git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Net,
+            "failed to connect",
+        ))
+    }
+
+    #[test]
+    fn retries_transient_errors() {
+        let mut attempts = 0;
+        let result = policy(3).run(|| {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(network_error()),
+                _ => Ok(attempts),
+            }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_retries() {
+        let mut attempts = 0;
+        let result: errors::Result<()> = policy(2).run(|| {
+            attempts += 1;
+            Err(network_error())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn no_retry_for_permanent_errors() {
+        let mut attempts = 0;
+        let result: errors::Result<()> = policy(3).run(|| {
+            attempts += 1;
+            Err(errors::Error::InvalidUsernamePassword)
+        });
+
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn classify_errors() {
+        let auth = git2::Error::new(git2::ErrorCode::Auth, git2::ErrorClass::Ssh, "denied");
+        let unavailable = ureq::Error::Status(503, ureq::Response::new(503, "", "").unwrap());
+        let not_found = ureq::Error::Status(404, ureq::Response::new(404, "", "").unwrap());
+
+        assert!(network_error().is_transient());
+        assert!(!errors::Error::FailedGitOperation(auth).is_transient());
+        assert!(errors::Error::FailedToDoHttpCall(Box::new(unavailable)).is_transient());
+        assert!(!errors::Error::FailedToDoHttpCall(Box::new(not_found)).is_transient());
+    }
+
+    #[test]
+    fn exponential_delay_with_jitter() {
+        let policy = RetryPolicy::builder()
+            .backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(1000))
+            .build();
+
+        let delay = policy.delay(3);
+        assert!(delay > Duration::from_millis(200) && delay <= Duration::from_millis(400));
+        assert!(policy.delay(10) <= Duration::from_millis(1000));
+    }
+}